@@ -0,0 +1,132 @@
+use std::fmt::Write;
+
+use ::base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serializes bytes as a base64 string, for use with `#[serde(with = "common::bytes::base64")]`
+pub mod base64 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        bytes: impl AsRef<[u8]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        STANDARD.decode(text).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Program input or output, which is usually but not always text.
+///
+/// Serialized as a plain string when it's valid UTF-8, and as `{"base64": "..."}` otherwise,
+/// so judges that only deal with text don't have to care about encodings.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ByteString(pub Vec<u8>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ByteStringRepr {
+    Text(String),
+    Bytes {
+        #[serde(with = "base64")]
+        base64: Vec<u8>,
+    },
+}
+
+impl ByteString {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The content as text, if it's valid UTF-8
+    pub fn as_text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+
+    /// Keeps at most `length` bytes. Text is cut before the character that crosses the limit,
+    /// so it still displays as text.
+    pub fn truncate(&mut self, mut length: usize) {
+        if length >= self.0.len() {
+            return;
+        }
+        if let Some(text) = self.as_text() {
+            while !text.is_char_boundary(length) {
+                length -= 1;
+            }
+        }
+        self.0.truncate(length);
+    }
+
+    /// The content as text if it's valid UTF-8, and as a hex dump otherwise. The second value
+    /// is `true` for hex dumps.
+    pub fn display(&self) -> (String, bool) {
+        match self.as_text() {
+            Some(text) => (text.to_owned(), false),
+            None => (hex_dump(&self.0), true),
+        }
+    }
+}
+
+impl From<String> for ByteString {
+    fn from(value: String) -> Self {
+        ByteString(value.into_bytes())
+    }
+}
+
+impl From<Vec<u8>> for ByteString {
+    fn from(value: Vec<u8>) -> Self {
+        ByteString(value)
+    }
+}
+
+impl Serialize for ByteString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.as_text() {
+            Some(text) => text.serialize(serializer),
+            None => ByteStringRepr::Bytes {
+                base64: self.0.clone(),
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match ByteStringRepr::deserialize(deserializer)? {
+            ByteStringRepr::Text(text) => text.into(),
+            ByteStringRepr::Bytes { base64 } => base64.into(),
+        })
+    }
+}
+
+/// Formats bytes like `xxd`: an offset, 16 bytes in hex, and the printable ASCII characters
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for (index, line) in bytes.chunks(16).enumerate() {
+        write!(output, "{:08x}: ", index * 16).unwrap();
+        for pair in line.chunks(2) {
+            for byte in pair {
+                write!(output, "{byte:02x}").unwrap();
+            }
+            output.push(' ');
+        }
+        // Pad short lines so the ASCII column stays aligned
+        let written = line.len() * 2 + line.len().div_ceil(2);
+        output.push_str(&" ".repeat(40 - written));
+        output.push(' ');
+        output.extend(line.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        output.push('\n');
+    }
+    output
+}
@@ -1,109 +1,266 @@
-use phf::phf_map;
-use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::scoring::Scoring;
 
-#[derive(Serialize)]
-#[serde(rename_all(serialize = "camelCase"))]
+/// Placeholders the runner replaces in commands and environment variables
+const PLACEHOLDERS: &[&str] = &["LANG_LOCATION", "FILE_LOCATION", "OUTPUT_LOCATION"];
+
+/// Replaced by the command line arguments the judge passes to the program, which can be
+/// none. It must be a whole argument of the run command. When the run command doesn't
+/// contain it, the arguments are appended.
+const ARGS_PLACEHOLDER: &str = "${ARGS}";
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct Lang {
-    pub plugin_name: &'static str,
-    pub display_name: &'static str,
-    pub compile_command: &'static [&'static str],
-    pub run_command: &'static [&'static str],
-    pub plugin: &'static str,
-    pub env: &'static [(&'static str, &'static str)],
-    pub install_env: &'static [(&'static str, &'static str)],
-    pub latest_version: &'static str,
-    pub icon: &'static str,
+    pub plugin_name: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub compile_command: Vec<String>,
+    pub run_command: Vec<String>,
+    pub plugin: String,
+    #[serde(default)]
+    pub env: Vec<(String, String)>,
+    #[serde(default)]
+    pub install_env: Vec<(String, String)>,
+    pub latest_version: String,
+    /// Older versions solutions may still use, besides `latestVersion`. Any other version can
+    /// only be used once an admin installs it.
+    #[serde(default)]
+    pub versions: Vec<String>,
+    pub icon: String,
+    /// The challenge time limit is multiplied by this, to account for languages that are slow to
+    /// start or need to compile
+    #[serde(default = "default_time_limit_multiplier")]
+    pub time_limit_multiplier: f32,
+    /// The script in `/scripts` that runs judges written in this language. Languages
+    /// without one can't be used for judges.
+    #[serde(default)]
+    pub judge_runner: Option<String>,
+    /// How solutions in this language are scored
+    #[serde(default)]
+    pub scoring: Scoring,
+    /// Host files and directories the language needs besides its own installation, like a
+    /// linker or system headers. The sandbox only has these and the system libraries.
+    #[serde(default)]
+    pub sandbox_paths: Vec<String>,
+    /// Versions installed from a tarball in the runner's artifacts directory instead of with
+    /// asdf, so they can be installed without internet access
+    #[serde(default)]
+    pub artifacts: BTreeMap<String, Artifact>,
+}
+
+/// A tarball of the installation directory of a version, with its contents at the top level
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Artifact {
+    /// The path of the tarball, relative to the artifacts directory
+    pub file: String,
+    /// The hex encoded SHA-256 of the tarball, installs fail if it doesn't match
+    pub sha256: String,
+}
+
+fn default_time_limit_multiplier() -> f32 {
+    1.0
+}
+
+/// Versions are passed to asdf as arguments, so they're limited to characters that can't be
+/// mistaken for an option or a path
+pub fn is_valid_version(version: &str) -> bool {
+    !version.is_empty()
+        && version.len() <= 64
+        && !version.starts_with(['-', '.'])
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-'))
 }
 
-pub const LANGS: phf::Map<&'static str, Lang> = phf_map! {
-    "nodejs" => Lang {
-        plugin_name: "nodejs",
-        display_name: "JavaScript (NodeJS)",
-        compile_command: &[],
-        run_command: &["${LANG_LOCATION}/bin/node", "${FILE_LOCATION}"],
-        plugin: "https://github.com/asdf-vm/asdf-nodejs.git",
-        env: &[],
-        install_env: &[],
-        latest_version: "22.9.0",
-        icon: "nodejs.svg"
-    },
-    "deno" => Lang {
-        plugin_name: "deno",
-        display_name: "JavaScript (Deno)",
-        compile_command: &[],
-        run_command: &["${LANG_LOCATION}/bin/deno", "--allow-write=/tmp", "--allow-run", "--allow-read", "${FILE_LOCATION}"],
-        //run_command: &["/usr/bin/env"],
-        plugin: "https://github.com/asdf-community/asdf-deno.git",
-        env: &[
-            ("RUST_BACKTRACE", "1"),
-            ("NO_COLOR", "1")
-        ],
-        install_env: &[],
-        latest_version: "2.0.6",
-        icon: "deno.svg"
-    },
-    "python" => Lang {
-        plugin_name: "python",
-        display_name: "Python",
-        compile_command: &[],
-        run_command: &["${LANG_LOCATION}/bin/python", "${FILE_LOCATION}"],
-        plugin: "https://github.com/asdf-community/asdf-python.git",
-        env: &[("LD_LIBRARY_PATH", "/lang/lib")],
-        install_env: &[],
-        latest_version: "3.12.0",
-        icon: "python.svg"
-    },
-    "rust" => Lang {
-        plugin_name: "rust",
-        display_name: "Rust",
-        compile_command: &["${LANG_LOCATION}/bin/rustc", "${FILE_LOCATION}", "-o", "${OUTPUT_LOCATION}"],
-        run_command: &["${OUTPUT_LOCATION}"],
-        plugin: "https://github.com/asdf-community/asdf-rust.git",
-        env: &[
-            ("LD_LIBRARY_PATH", "/lang/lib:/lib"),
-            ("PATH", "/usr/bin:/bin")
-        ],
-        install_env: &[(
-            "RUST_WITHOUT",
-            "rust-docs,rust-docs-json-preview,cargo,rustfmt-preview,rls-preview,rust-analyzer-preview,llvm-tools-preview,clippy-preview,rust-analysis-x86_64-unknown-linux-gnu,llvm-bitcode-linker-preview"
-        )],
-        latest_version: "1.82.0",
-        icon: "rust.svg"
-    },
-    "vyxal" => Lang {
-        plugin_name: "vyxal",
-        display_name: "Vyxal",
-        compile_command: &[],
-        run_command: &["${LANG_LOCATION}/bin/vyxal2", "${FILE_LOCATION}", "'□'"],
-        plugin: "https://github.com/lyxal/vyxasdf.git",
-        env: &[],
-        install_env: &[],
-        latest_version: "2.22.4.3",
-        icon: "vyxal.svg"
-    },
-    "tinyapl" => Lang {
-        plugin_name: "tinyapl",
-        display_name: "APL (TinyAPL)",
-        compile_command: &[],
-        run_command: &["${LANG_LOCATION}/bin/tinyapl", "${FILE_LOCATION}"],
-        plugin: "https://github.com/RubenVerg/asdf-tinyapl.git",
-        env: &[],
-        install_env: &[],
-        latest_version: "0.11.1.0",
-        icon: "tinyapl.svg"
-    },
-    "tcc" => Lang {
-        plugin_name: "tcc",
-        display_name: "C (tcc)",
-        compile_command: &[],
-        run_command: &["${LANG_LOCATION}/bin/tcc", "-run", "-B", "${LANG_LOCATION}/lib/tcc", "${FILE_LOCATION}"],
-        plugin: "https://github.com/mousetail/asdf-plugin-tcc.git",
-        env: &[
-            ("C_INCLUDE_PATH", "{LANG_LOCATION}/include"),
-            ("LIBRARY_PATH", "${LANG_LOCATION}/lib")
-        ],
-        install_env: &[],
-        latest_version: "0.9.27",
-        icon: "c.svg"
+impl Lang {
+    /// Whether solutions may use this version without an admin installing it first
+    pub fn allows_version(&self, version: &str) -> bool {
+        self.latest_version == version || self.versions.iter().any(|v| v == version)
     }
-};
+
+    fn validate(&self) -> Result<(), String> {
+        for (field, value) in [
+            ("pluginName", &self.plugin_name),
+            ("displayName", &self.display_name),
+            ("plugin", &self.plugin),
+            ("latestVersion", &self.latest_version),
+            ("icon", &self.icon),
+        ]
+        .into_iter()
+        .chain(
+            self.judge_runner
+                .as_ref()
+                .map(|runner| ("judgeRunner", runner)),
+        ) {
+            if value.is_empty() {
+                return Err(format!("{field} must not be empty"));
+            }
+        }
+        if self.run_command.is_empty() {
+            return Err("runCommand must not be empty".to_string());
+        }
+        if self.time_limit_multiplier.is_nan() || self.time_limit_multiplier <= 0.0 {
+            return Err("timeLimitMultiplier must be positive".to_string());
+        }
+
+        if let Some(version) = std::iter::once(&self.latest_version)
+            .chain(&self.versions)
+            .find(|version| !is_valid_version(version))
+        {
+            return Err(format!("Invalid version {version:?}"));
+        }
+
+        for (version, artifact) in &self.artifacts {
+            if !is_valid_version(version) {
+                return Err(format!("Invalid artifact version {version:?}"));
+            }
+            if artifact.file.is_empty()
+                || Path::new(&artifact.file)
+                    .components()
+                    .any(|component| !matches!(component, Component::Normal(_)))
+            {
+                return Err(format!(
+                    "The artifact of {version} must be a path inside the artifacts directory"
+                ));
+            }
+            if artifact.sha256.len() != 64
+                || !artifact.sha256.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return Err(format!(
+                    "The sha256 of the artifact of {version} isn't valid"
+                ));
+            }
+        }
+
+        if let Some(path) = self
+            .sandbox_paths
+            .iter()
+            .find(|path| !path.starts_with('/'))
+        {
+            return Err(format!("sandboxPaths must be absolute, {path:?} isn't"));
+        }
+
+        if self
+            .run_command
+            .iter()
+            .filter(|arg| *arg == ARGS_PLACEHOLDER)
+            .count()
+            > 1
+        {
+            return Err(format!("{ARGS_PLACEHOLDER} can only be used once"));
+        }
+
+        self.compile_command
+            .iter()
+            .chain(
+                self.run_command
+                    .iter()
+                    .filter(|arg| *arg != ARGS_PLACEHOLDER),
+            )
+            .chain(self.env.iter().map(|(_, value)| value))
+            .try_for_each(|value| check_placeholders(value))
+    }
+}
+
+fn check_placeholders(value: &str) -> Result<(), String> {
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            return Err(format!("Unclosed placeholder in {value:?}"));
+        };
+        let name = &after[..end];
+        if name == "ARGS" {
+            return Err(format!(
+                "{ARGS_PLACEHOLDER} must be a whole argument of runCommand in {value:?}"
+            ));
+        }
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!("Unknown placeholder ${{{name}}} in {value:?}"));
+        }
+        rest = &after[end + 1..];
+    }
+    Ok(())
+}
+
+/// All languages by name, as defined in the languages file
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Langs(BTreeMap<String, Lang>);
+
+impl Langs {
+    pub fn get(&self, name: &str) -> Option<&Lang> {
+        self.0.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Lang)> {
+        self.0.iter()
+    }
+
+    fn load() -> Result<Self, LangsError> {
+        let path = langs_file();
+        let text = std::fs::read_to_string(&path).map_err(|e| LangsError::Read(path.clone(), e))?;
+        let langs: Langs = serde_json::from_str(&text).map_err(|e| LangsError::Parse(path, e))?;
+        for (name, lang) in langs.iter() {
+            lang.validate().map_err(|reason| LangsError::Invalid {
+                lang: name.clone(),
+                reason,
+            })?;
+        }
+        Ok(langs)
+    }
+}
+
+/// The languages file is shared by the lang runner and the main server. Its location is
+/// read from `YQ_LANGS_FILE`.
+fn langs_file() -> PathBuf {
+    std::env::var_os("YQ_LANGS_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("scripts/langs.json"))
+}
+
+#[derive(Debug)]
+pub enum LangsError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Invalid { lang: String, reason: String },
+}
+
+impl Display for LangsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LangsError::Read(path, e) => write!(f, "Failed to read {path:?}: {e}"),
+            LangsError::Parse(path, e) => write!(f, "Failed to parse {path:?}: {e}"),
+            LangsError::Invalid { lang, reason } => write!(f, "Invalid language {lang}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for LangsError {}
+
+static LANGS: LazyLock<RwLock<Arc<Langs>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(Langs::load().unwrap_or_else(|e| panic!("{e}")))));
+
+/// The currently loaded languages. Panics if the languages file is invalid the first time
+/// it is read.
+pub fn langs() -> Arc<Langs> {
+    LANGS.read().unwrap().clone()
+}
+
+/// Reads the languages file again. The current languages stay in use if the new file
+/// is invalid.
+pub fn reload_langs() -> Result<(), LangsError> {
+    let langs = Langs::load()?;
+    *LANGS.write().unwrap() = Arc::new(langs);
+    Ok(())
+}
@@ -1,23 +1,177 @@
+pub mod bytes;
 pub mod langs;
+pub mod scoring;
+pub mod signature;
 
+use std::fmt::Display;
+
+use bytes::ByteString;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct JudgeResult {
     pub pass: bool,
     pub test_cases: Vec<TestCase>,
+    /// The judge promised the same inputs always give the same result, so it may be cached
+    #[serde(default)]
+    pub deterministic: bool,
+    /// Score reported by the judge, used for challenges that are scored by their judge
+    #[serde(default)]
+    pub score: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RunLangOutput {
     pub tests: JudgeResult,
     pub stderr: String,
     pub timed_out: bool,
+    #[serde(default)]
+    pub limit_exceeded: Option<LimitExceeded>,
+    /// Hash of everything that can affect the result of the run
+    #[serde(default)]
+    pub input_hash: Option<String>,
+    /// This result was reused from an earlier identical run
+    #[serde(default)]
+    pub cached: bool,
+    /// Resources used by the whole judge, including every program it ran
+    #[serde(default)]
+    pub usage: ResourceUsage,
+    /// Wall time of the programs the judge ran, in the fastest benchmark run. Only set for
+    /// benchmarks.
+    #[serde(default)]
+    pub runtime_ms: Option<f64>,
+}
+
+/// Time and memory used by a process and all its children
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceUsage {
+    pub wall_time_ms: f64,
+    /// User plus system time, if it could be measured
+    #[serde(default)]
+    pub cpu_time_ms: Option<f64>,
+    /// Peak resident set size, if it could be measured
+    #[serde(default)]
+    pub peak_memory_kib: Option<u64>,
 }
 
+/// Like "12.3 ms wall time, 10.0 ms CPU time, 5.2 MiB peak memory", leaving out what
+/// wasn't measured
+impl Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.1} ms wall time", self.wall_time_ms)?;
+        if let Some(cpu_time_ms) = self.cpu_time_ms {
+            write!(f, ", {cpu_time_ms:.1} ms CPU time")?;
+        }
+        if let Some(peak_memory_kib) = self.peak_memory_kib {
+            write!(
+                f,
+                ", {:.1} MiB peak memory",
+                peak_memory_kib as f64 / 1024.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A resource limit that stopped a run before it could finish
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitExceeded {
+    /// The run took longer than the wall clock limit
+    TimedOut,
+    /// The sandbox used more memory than allowed and was killed by the OOM killer
+    MemoryExceeded,
+    /// The sandbox tried to create more processes or threads than allowed
+    PidsExceeded,
+    /// A process tried to write a file larger than allowed
+    FileSizeExceeded,
+    /// A process used more CPU time than allowed
+    CpuTimeExceeded,
+}
+
+impl LimitExceeded {
+    pub fn description(self) -> &'static str {
+        match self {
+            LimitExceeded::TimedOut => "Code timed out",
+            LimitExceeded::MemoryExceeded => "Code used too much memory",
+            LimitExceeded::PidsExceeded => "Code started too many processes or threads",
+            LimitExceeded::FileSizeExceeded => "Code tried to write a file that was too large",
+            LimitExceeded::CpuTimeExceeded => "Code used too much CPU time",
+        }
+    }
+}
+
+/// Decides which queued runs the lang runner starts first
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunPriority {
+    /// Someone is waiting for the result
+    #[default]
+    Interactive,
+    /// Re-testing existing solutions after a challenge changed
+    Revalidation,
+}
+
+/// A single line of the streaming output of the lang runner
 #[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RunLangEvent {
+    /// Sent if the run has to wait for other runs first
+    Queued { position: usize },
+    /// Sent as soon as the judge outputs a test case
+    TestCase(TestCase),
+    /// Always the last event of a successful run, contains all test cases again
+    Done(RunLangOutput),
+    /// The run failed before the judge could finish
+    Error(RunnerError),
+}
+
+/// Why the lang runner couldn't run a job. This is the JSON body of its error responses.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RunnerError {
+    pub code: RunnerErrorCode,
+    /// Details for the logs, not meant for users
+    pub message: String,
+    /// Whether the same job might work on another runner, or on this one later
+    pub retryable: bool,
+}
+
+/// Stable codes for the errors of the lang runner
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunnerErrorCode {
+    UnknownLanguage,
+    /// The version isn't allowed for the language and no admin has installed it
+    VersionNotAllowed,
+    InvalidVersion,
+    /// The version is being installed or uninstalled, or jobs are using it
+    ToolchainBusy,
+    NotInstalled,
+    UninstallFailed,
+    NotAJudgeLanguage,
+    InstallFailed,
+    SandboxSpawnFailed,
+    /// The runner is shutting down and doesn't start new runs
+    SemaphoreClosed,
+    Internal,
+    /// The request wasn't signed with the shared secret, see `signature`
+    Unauthorized,
+    BodyTooLarge,
+    /// A code added by a newer lang runner
+    #[serde(other)]
+    Unknown,
+}
+
+impl Display for RunnerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub enum TestPassState {
     /// The test passed
     Pass,
@@ -30,7 +184,7 @@ pub enum TestPassState {
     Warning,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct TestCase {
     #[serde(default)]
@@ -45,22 +199,87 @@ impl TestCase {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum ResultDisplay {
     Empty,
     Text(String),
     Diff {
-        output: String,
-        expected: String,
+        output: ByteString,
+        expected: ByteString,
     },
     Run {
         #[serde(default)]
-        input: Option<String>,
-        output: String,
-        error: String,
+        input: Option<ByteString>,
+        output: ByteString,
+        error: ByteString,
+        /// Resources used by the program, set by the judge runner
+        #[serde(default)]
+        usage: Option<ResourceUsage>,
+        /// Command line arguments passed to the program
+        #[serde(default)]
+        args: Vec<String>,
+        /// Files placed in the working directory of the program before the run
+        #[serde(default)]
+        files: Vec<InputFile>,
+    },
+    /// An interactive session, where the judge and the program take turns
+    Transcript {
+        entries: Vec<TranscriptEntry>,
+        #[serde(default)]
+        error: ByteString,
+        #[serde(default)]
+        usage: Option<ResourceUsage>,
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        files: Vec<InputFile>,
     },
 }
 
+/// A file the judge gave to the program
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InputFile {
+    /// The file name within the working directory of the program
+    pub name: String,
+    pub content: ByteString,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TranscriptEntry {
+    pub source: TranscriptSource,
+    pub text: ByteString,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptSource {
+    /// The judge wrote this to the program
+    Judge,
+    /// The program wrote this to the judge
+    Program,
+    /// Something happened during the session, like a timeout
+    Note,
+}
+
+/// One line per entry, with `>` for what the judge sent, `<` for what the program replied and
+/// `#` for notes. Binary entries are shown lossily, since a session is mostly text.
+pub fn format_transcript(entries: &[TranscriptEntry]) -> String {
+    let mut output = String::new();
+    for entry in entries {
+        let prefix = match entry.source {
+            TranscriptSource::Judge => "> ",
+            TranscriptSource::Program => "< ",
+            TranscriptSource::Note => "# ",
+        };
+        let text = String::from_utf8_lossy(entry.text.as_bytes());
+        for line in text.strip_suffix('\n').unwrap_or(&text).split('\n') {
+            output.push_str(prefix);
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
 impl ResultDisplay {
     pub fn truncate(&mut self, length: usize) {
         match self {
@@ -74,12 +293,37 @@ impl ResultDisplay {
                 input,
                 output,
                 error,
+                usage: _,
+                args: _,
+                files,
             } => {
                 if let Some(input) = input {
                     input.truncate(length);
                 }
                 output.truncate(length);
                 error.truncate(length);
+                for file in files {
+                    file.content.truncate(length);
+                }
+            }
+            ResultDisplay::Transcript {
+                entries,
+                error,
+                files,
+                ..
+            } => {
+                for file in files {
+                    file.content.truncate(length);
+                }
+                // Long sessions have many short entries, so limit the total length
+                let mut remaining = length;
+                entries.retain_mut(|entry| {
+                    let keep = remaining > 0;
+                    entry.text.truncate(remaining);
+                    remaining -= entry.text.as_bytes().len();
+                    keep
+                });
+                error.truncate(length);
             }
         }
     }
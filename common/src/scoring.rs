@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+/// How the score of a solution is calculated from its code
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scoring {
+    /// The length of the code encoded as UTF-8
+    #[default]
+    Utf8Bytes,
+    /// The number of unicode characters
+    Chars,
+    /// One byte per character for languages with their own single byte codepage. Code
+    /// that uses characters outside the codepage is scored in UTF-8 bytes instead.
+    #[serde(untagged)]
+    Codepage(Codepage),
+}
+
+impl Scoring {
+    pub fn score(self, code: &str) -> usize {
+        match self {
+            Scoring::Utf8Bytes => code.len(),
+            Scoring::Chars => code.chars().count(),
+            Scoring::Codepage(codepage) => {
+                if code.chars().all(|c| codepage.contains(c)) {
+                    code.chars().count()
+                } else {
+                    code.len()
+                }
+            }
+        }
+    }
+}
+
+/// A named single byte character set
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Codepage {
+    Vyxal,
+    /// ASCII plus the APL glyphs
+    Apl,
+}
+
+/// In codepage order, from 0x00 to 0xff
+const VYXAL_CODEPAGE: &str = concat!(
+    "λƛ¬∧⟑∨⟇÷×«\n»°•ß†",
+    "€½∆ø↔¢⌐æʀʁɾɽÞƈ∞¨",
+    " !\"#$%&'()*+,-./",
+    "0123456789:;<=>?",
+    "@ABCDEFGHIJKLMNO",
+    "PQRSTUVWXYZ[\\]`^",
+    "_abcdefghijklmno",
+    "pqrstuvwxyz{|}~↑",
+    "↓∴∵›‹∷¤ð→←βτȧḃċḋ",
+    "ėḟġḣḭŀṁṅȯṗṙṡṫẇẋẏ",
+    "ż√⟨⟩‛₀₁₂₃₄₅₆₇₈¶⁋",
+    "§ε¡∑¦≈µȦḂĊḊĖḞĠḢİ",
+    "ĿṀṄȮṖṘṠṪẆẊẎŻ₌₍⁰¹",
+    "²∇⌈⌊¯±₴…□↳↲⋏⋎꘍ꜝ℅",
+    "≤≥≠⁼ƒɖ∪∩⊍£¥⇧⇩ǍǎǏ",
+    "ǐǑǒǓǔ⁽‡≬⁺↵⅛¼¾Π„‟",
+);
+
+const APL_GLYPHS: &str = "¨¯×÷←↑→↓∆∇∊∘∣∧∨∩∪∼≠≡≢≤≥⊂⊃⊆⊖⊢⊣⊤⊥⌈⌊⌶⌷⌸⌹⌺⌽⌿⍀⍉⍋⍎⍒⍕⍙⍝⍞⍟⍠⍣⍤⍥⍨⍪⍬⍱⍲⍳⍴⍵⍶⍷⍸⍹⍺⎕○⋄⍛⌾⍫⍢⍡⌻⌼⍁⍂⍃⍄⍅⍆⍇⍈⍊⍌⍍⍏⍐⍑⍓⍔⍖⍗⍘⍚⍜⍦⍧⍩⍮⍯⍰";
+
+impl Codepage {
+    pub fn contains(self, c: char) -> bool {
+        match self {
+            Codepage::Vyxal => VYXAL_CODEPAGE.contains(c),
+            Codepage::Apl => {
+                c == '\n' || c == ' ' || c.is_ascii_graphic() || APL_GLYPHS.contains(c)
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use common::langs::LANGS;
+use common::langs::langs;
 use serenity::all::{
     ChannelId, CreateEmbed, CreateEmbedAuthor, CreateMessage, EditMessage, MessageId,
 };
@@ -9,7 +9,7 @@ pub struct ScoreImproved {
     pub challenge_id: i32,
     pub author: i32,
     pub language: String,
-    pub score: i32,
+    pub score: f64,
 }
 
 struct LastMessage {
@@ -20,10 +20,10 @@ struct LastMessage {
     challenge_id: i32,
     author_id: i32,
     author_name: String,
-    score: i32,
+    score: f64,
     previous_author_id: Option<i32>,
     previous_author_name: Option<String>,
-    previous_author_score: Option<i32>,
+    previous_author_score: Option<f64>,
     message_id: i64,
     channel_id: i64,
 }
@@ -44,10 +44,9 @@ fn format_message(
     let mut embed = CreateEmbed::new()
         .title(format!(
             "Improved score for {challenge_name} in {}",
-            LANGS
+            langs()
                 .get(&new_message.language)
-                .map(|d| d.display_name)
-                .unwrap_or(&new_message.language)
+                .map_or(&new_message.language, |d| &d.display_name)
         ))
         .author(
             CreateEmbedAuthor::new(&author.username)
@@ -87,7 +86,7 @@ async fn save_new_message_info(
     message: ScoreImproved,
     message_id: i64,
     last_author_id: Option<i32>,
-    last_score: Option<i32>,
+    last_score: Option<f64>,
     final_channel_id: i64,
 ) -> Result<(), sqlx::Error> {
     match &last_message {
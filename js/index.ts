@@ -9,7 +9,12 @@ import { WorkerShape } from "@valtown/codemirror-ts/worker";
 import * as Comlink from "comlink";
 import { StateEffect } from "@codemirror/state";
 import "./style.css";
-import { renderResultDisplay, ResultDisplay } from "./test_case";
+import {
+  renderResultDisplay,
+  renderTestCase,
+  ResultDisplay,
+  Test,
+} from "./test_case";
 
 import { oneDark } from "@codemirror/theme-one-dark";
 import { basicLight } from "@fsegurai/codemirror-theme-basic-light";
@@ -82,19 +87,40 @@ const setupEditorControls = (
   editorControls.classList.remove("hidden");
   const byteCountElement = editorControls.querySelector("#byte-counter")!;
   const resetButton = editorControls.querySelector("#restore-solution-button")!;
-  const textEncoder = new TextEncoder();
   let originalText = mainTextArea.state.doc.toString();
-  const lengthInBytes = (s: string): number => textEncoder.encode(s).length;
+  const languageName = window.location.pathname.split("/").pop();
+
+  // Languages with their own codepage are scored differently from UTF-8, so the server
+  // calculates the score. Requests are debounced so typing doesn't send one per key.
+  let scoreTimeout: ReturnType<typeof setTimeout> | undefined;
+  let latestScoreRequest = 0;
+  const updateScore = async (code: string) => {
+    const request = ++latestScoreRequest;
+    const response = await fetch(`/lang/${languageName}/score`, {
+      method: "POST",
+      headers: {
+        accept: "application/json",
+        "content-type": "application/json",
+      },
+      body: JSON.stringify({ code: encodeBase64(code), encoding: "base64" }),
+    });
+    if (response.ok && request === latestScoreRequest) {
+      const { score }: { score: number } = await response.json();
+      byteCountElement.textContent = score.toString();
+    }
+  };
 
-  byteCountElement.textContent = lengthInBytes(originalText).toString();
+  updateScore(originalText);
 
   mainTextArea.dispatch({
     effects: StateEffect.appendConfig.of([
       EditorView.updateListener.of((update) => {
         if (update.docChanged) {
-          byteCountElement.textContent = lengthInBytes(
-            mainTextArea.state.doc.toString()
-          ).toString();
+          clearTimeout(scoreTimeout);
+          scoreTimeout = setTimeout(
+            () => updateScore(mainTextArea.state.doc.toString()),
+            200
+          );
         }
       }),
     ]),
@@ -168,6 +194,15 @@ window.addEventListener("load", async () => {
   }
 });
 
+/// Code is sent as base64 with `encoding: "base64"`, since it doesn't have to be valid UTF-8
+function encodeBase64(text: string): string {
+  let binary = "";
+  for (const byte of new TextEncoder().encode(text)) {
+    binary += String.fromCharCode(byte);
+  }
+  return btoa(binary);
+}
+
 /// Only works from the solutions page
 async function submitNewSolution(
   mainTextArea: EditorView,
@@ -178,43 +213,99 @@ async function submitNewSolution(
   try {
     const content = mainTextArea.state.doc.toString();
 
-    const response = await fetch(window.location.href, {
-      method: "POST",
-      headers: {
-        accept: "application/json",
-        "content-type": "application/json",
-      },
-      body: JSON.stringify({
-        code: content,
-      }),
-    });
+    const response = await fetch(
+      `${window.location.pathname}/stream${window.location.search}`,
+      {
+        method: "POST",
+        headers: {
+          accept: "application/x-ndjson",
+          "content-type": "application/json",
+        },
+        body: JSON.stringify({
+          code: encodeBase64(content),
+          encoding: "base64",
+          version: document.querySelector<HTMLSelectElement>(
+            "select.solution-version-select"
+          )?.value,
+        }),
+      }
+    );
 
     const errorDiv = document.querySelector(".solution-submit-error");
-    if (![200, 201, 400].includes(response.status)) {
+    if (!response.ok) {
       errorDiv.textContent = await response.text();
       errorDiv.classList.remove("hidden");
       return;
     }
     errorDiv.classList.add("hidden");
 
-    const { tests, leaderboard } = (await response.json()) as {
-      tests: ResultDisplay;
-      leaderboard: LeaderboardEntry[];
-    };
-    updateLeaderbaord(leaderboard);
-
-    if (tests.passed && response.status === 201) {
-      setOriginalText(content);
-    }
     const testsContainer = document.querySelector(
       "div.result-display-wrapper"
     ) as HTMLDivElement;
-    renderResultDisplay(tests, testsContainer);
+    const testCasesDiv = testsContainer.querySelector(".test-cases");
+    const passStateDiv = testsContainer.querySelector(".result-pass-state");
+    testCasesDiv.replaceChildren();
+    passStateDiv.textContent = "Running";
+    testsContainer.querySelector(".resource-usage").textContent = "";
+
+    for await (const event of readJsonLines<SolutionStreamEvent>(
+      response.body
+    )) {
+      if ("queued" in event) {
+        passStateDiv.textContent = `Queued, ${event.queued.position} runs ahead`;
+      } else if ("testCase" in event) {
+        passStateDiv.textContent = "Running";
+        testCasesDiv.appendChild(renderTestCase(event.testCase));
+      } else if ("done" in event) {
+        const { status, result } = event.done;
+        updateLeaderbaord(result.leaderboard);
+
+        if (result.tests.passed && status === 201) {
+          setOriginalText(content);
+        }
+        renderResultDisplay(result.tests, testsContainer);
+      } else {
+        errorDiv.textContent = event.error;
+        errorDiv.classList.remove("hidden");
+      }
+    }
   } finally {
     submitButton.disabled = false;
   }
 }
 
+type SolutionStreamEvent =
+  | { queued: { position: number } }
+  | { testCase: Test }
+  | {
+      done: {
+        status: number;
+        result: { tests: ResultDisplay; leaderboard: LeaderboardEntry[] };
+      };
+    }
+  | { error: string };
+
+async function* readJsonLines<T>(
+  body: ReadableStream<Uint8Array>
+): AsyncGenerator<T> {
+  const reader = body.pipeThrough(new TextDecoderStream()).getReader();
+  let buffer = "";
+  while (true) {
+    const { done, value } = await reader.read();
+    if (done) {
+      break;
+    }
+    buffer += value;
+    const lines = buffer.split("\n");
+    buffer = lines.pop();
+    for (const line of lines) {
+      if (line !== "") {
+        yield JSON.parse(line) as T;
+      }
+    }
+  }
+}
+
 function setupJsSubmitOnForm(
   mainTextArea: EditorView,
   setOriginalText: (e: string) => void
@@ -236,31 +327,43 @@ function setupLeaderboardForm(form: HTMLFormElement) {
     ev.preventDefault();
   });
 
-  form.querySelectorAll("button").forEach((button) => {
-    const languageName = window.location.pathname.split("/").pop();
+  const languageName = window.location.pathname.split("/").pop();
+  const versionSelect = form.querySelector<HTMLSelectElement>(
+    "select.leaderboard-version-select"
+  );
 
-    button.addEventListener("click", async () => {
-      changeActiveLeaderboardTab(button.value);
+  const refresh = async (ranking: string) => {
+    const query = new URLSearchParams({ ranking });
+    if (versionSelect && versionSelect.value !== "") {
+      query.set("version", versionSelect.value);
+    }
 
-      const response = await fetch(
-        `../leaderboard/${languageName}?ranking=${encodeURIComponent(
-          button.value
-        )}`,
-        {
-          headers: {
-            accept: "application/json",
-            "content-type": "application/json",
-          },
-        }
-      );
+    const response = await fetch(`../leaderboard/${languageName}?${query}`, {
+      headers: {
+        accept: "application/json",
+        "content-type": "application/json",
+      },
+    });
 
-      if (!response.ok) {
-        console.error(await response.json());
-      }
+    if (!response.ok) {
+      console.error(await response.json());
+    }
+
+    updateLeaderbaord(await response.json());
+  };
 
-      updateLeaderbaord(await response.json());
+  form.querySelectorAll("button").forEach((button) => {
+    button.addEventListener("click", async () => {
+      changeActiveLeaderboardTab(button.value);
+      await refresh(button.value);
     });
   });
+
+  versionSelect?.addEventListener("change", async () => {
+    const activeButton =
+      form.querySelector<HTMLButtonElement>("button.active");
+    await refresh(activeButton?.value ?? "top");
+  });
 }
 
 type LeaderboardEntry = {
@@ -269,6 +372,7 @@ type LeaderboardEntry = {
   author_name: string;
   author_id: number;
   score: number;
+  version: string;
 };
 
 function updateLeaderbaord(ranking: LeaderboardEntry[]) {
@@ -295,6 +399,10 @@ function updateLeaderbaord(ranking: LeaderboardEntry[]) {
       authorNameCell.appendChild(link);
       row.appendChild(authorNameCell);
 
+      const versionCell = document.createElement("td");
+      versionCell.textContent = entry.version;
+      row.appendChild(versionCell);
+
       const scoreCell = document.createElement("td");
       scoreCell.textContent = `${entry.score}`;
       row.appendChild(scoreCell);
@@ -5,17 +5,22 @@ export type ResultDisplay = {
   passed: boolean;
   tests: Test[];
   timedOut: boolean;
+  limitExceeded: null | string;
+  cached: boolean;
+  usage: string;
 };
 
-type Test = {
+export type Test = {
   columns: Column[];
   status: string;
   title: string | null;
+  usage: string | null;
 };
 
 type Column = {
   content: string;
   title: string | null;
+  hex: boolean;
 };
 
 export function renderResultDisplay(
@@ -23,14 +28,25 @@ export function renderResultDisplay(
   parent: HTMLDivElement
 ) {
   const resultPassStateDiv = parent.querySelector(".result-pass-state");
+  const resourceUsageDiv = parent.querySelector(".resource-usage");
   const timeOutWarningDiv = parent.querySelector(".time-out-warning");
+  const limitExceededWarningDiv = parent.querySelector(
+    ".limit-exceeded-warning"
+  );
   const judgeErrorsDiv = parent.querySelector(".judge-errors");
   const testCasesDiv = parent.querySelector(".test-cases");
 
   resultPassStateDiv.textContent = display.passed ? "Pass" : "Fail";
+  resourceUsageDiv.textContent = display.usage;
 
   timeOutWarningDiv.classList.toggle("hidden", !display.timedOut);
 
+  limitExceededWarningDiv.classList.toggle(
+    "hidden",
+    display.limitExceeded === null
+  );
+  limitExceededWarningDiv.textContent = display.limitExceeded ?? "";
+
   judgeErrorsDiv.classList.toggle("hidden", display.judgeError === null);
   if (display.judgeError !== null) {
     judgeErrorsDiv.querySelector("pre").textContent = display.judgeError;
@@ -39,7 +55,7 @@ export function renderResultDisplay(
   testCasesDiv.replaceChildren(...display.tests.map(renderTestCase));
 }
 
-function renderTestCase(testCase: Test): HTMLDivElement {
+export function renderTestCase(testCase: Test): HTMLDivElement {
   const root = document.createElement("div");
   root.classList.add("test-case", `test-${testCase.status.toLowerCase()}`);
 
@@ -52,6 +68,13 @@ function renderTestCase(testCase: Test): HTMLDivElement {
     header.appendChild(title);
   }
 
+  if (testCase.usage) {
+    const usage = document.createElement("div");
+    usage.classList.add("resource-usage");
+    usage.textContent = testCase.usage;
+    header.appendChild(usage);
+  }
+
   const status = document.createElement("div");
   status.classList.add("test-case-status");
   status.textContent = testCase.status;
@@ -82,12 +105,13 @@ function renderColumn(column: Column): HTMLDivElement {
 
   if (column.title) {
     let title = document.createElement("h3");
-    title.textContent = column.title;
+    title.textContent = column.hex ? `${column.title} (hex)` : column.title;
     columnDiv.appendChild(title);
   }
 
   const pre = document.createElement("pre");
   pre.classList.add("code-pre");
+  pre.classList.toggle("hex-view", column.hex);
   pre.textContent = column.content;
   columnDiv.appendChild(pre);
 
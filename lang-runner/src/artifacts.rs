@@ -0,0 +1,107 @@
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use async_process::Command;
+use common::langs::{Artifact, Lang};
+use sha2::{Digest, Sha256};
+
+use crate::{config::env_or, error::RunProcessError};
+
+/// The local mirror toolchains are installed from, from `YQ_ARTIFACTS_DIR`. It has the tarballs
+/// of the `artifacts` of languages, and optionally git repositories of asdf plugins in
+/// `plugins/<plugin name>`.
+pub static ARTIFACTS_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    std::env::var_os("YQ_ARTIFACTS_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/artifacts"))
+});
+
+/// Only install versions that have an artifact and plugins from the artifacts directory, from
+/// `YQ_OFFLINE`. For hosts without internet access, and for builds that must be reproducible.
+pub static OFFLINE: LazyLock<bool> = LazyLock::new(|| env_or("YQ_OFFLINE", false));
+
+/// Where asdf keeps plugins and installed versions
+pub fn asdf_data_dir() -> PathBuf {
+    std::env::var_os("ASDF_DATA_DIR")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".asdf")))
+        .unwrap_or_else(|| PathBuf::from(".asdf"))
+}
+
+/// The plugin repository in the artifacts directory if there is one, the `plugin` of the language
+/// otherwise
+pub fn plugin_source(lang: &Lang) -> Result<String, RunProcessError> {
+    let mirrored = ARTIFACTS_DIR.join("plugins").join(&lang.plugin_name);
+    if mirrored.is_dir() {
+        return Ok(mirrored.to_string_lossy().into_owned());
+    }
+    if *OFFLINE {
+        return Err(RunProcessError::NotMirrored(mirrored.display().to_string()));
+    }
+    Ok(lang.plugin.clone())
+}
+
+fn sha256_of(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Checks the tarball against its pinned checksum, then extracts it where asdf expects the
+/// version. `log` gets a line for every step.
+pub async fn install_artifact(
+    plugin_name: &str,
+    version: &str,
+    artifact: &Artifact,
+    log: impl Fn(&str),
+) -> Result<(), RunProcessError> {
+    let file = ARTIFACTS_DIR.join(&artifact.file);
+    log(&format!("Verifying {}", file.display()));
+    let actual = {
+        let file = file.clone();
+        tokio::task::spawn_blocking(move || sha256_of(&file))
+            .await
+            .expect("Hashing doesn't panic")?
+    };
+    if !actual.eq_ignore_ascii_case(&artifact.sha256) {
+        return Err(RunProcessError::ChecksumMismatch {
+            file,
+            expected: artifact.sha256.clone(),
+            actual,
+        });
+    }
+
+    // Extracted next to the final directory and moved in place at the end, so asdf never sees
+    // a partial install
+    let plugin_dir = asdf_data_dir().join("installs").join(plugin_name);
+    std::fs::create_dir_all(&plugin_dir)?;
+    let extracted = tempfile::Builder::new()
+        .prefix(&format!(".{version}-"))
+        .tempdir_in(&plugin_dir)?;
+    log(&format!("Extracting {}", file.display()));
+    let status = Command::new("tar")
+        .arg("--extract")
+        .arg("--file")
+        .arg(&file)
+        .arg("--directory")
+        .arg(extracted.path())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(RunProcessError::NonZeroStatusCode(status.code()));
+    }
+
+    let install_dir = plugin_dir.join(version);
+    if install_dir.exists() {
+        std::fs::remove_dir_all(&install_dir)?;
+    }
+    // Dropping `extracted` afterwards finds nothing left to remove, and removes the partial
+    // extraction if anything before fails
+    std::fs::rename(extracted.path(), &install_dir)?;
+    log(&format!("Installed {version} in {}", install_dir.display()));
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::{
+    sync::LazyLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use common::{
+    signature::{verify, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+    RunnerError, RunnerErrorCode,
+};
+
+use crate::config::env_or;
+
+/// The secret shared with the main server, from `YQ_RUNNER_SECRET`. It's removed from the
+/// environment once read, so the installs and sandboxes the runner starts can't see it. `main`
+/// reads it before starting any other thread.
+pub static RUNNER_SECRET: LazyLock<String> = LazyLock::new(|| {
+    let secret = std::env::var("YQ_RUNNER_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .expect("Missing the YQ_RUNNER_SECRET environment variable");
+    std::env::remove_var("YQ_RUNNER_SECRET");
+    secret
+});
+
+/// How far the timestamp of a request may be from now, from `YQ_SIGNATURE_MAX_AGE_SECS`.
+/// Signed requests can be replayed within this time.
+static MAX_AGE_SECS: LazyLock<u64> = LazyLock::new(|| env_or("YQ_SIGNATURE_MAX_AGE_SECS", 60));
+
+/// The same as the default limit of the `Json` extractor
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Why a request was rejected
+#[derive(Debug, Clone, Copy)]
+pub enum AuthError {
+    MissingSignature,
+    MalformedTimestamp,
+    ExpiredTimestamp,
+    BodyTooLarge,
+    InvalidSignature,
+}
+
+impl AuthError {
+    fn message(self) -> &'static str {
+        match self {
+            AuthError::MissingSignature => "The request isn't signed",
+            AuthError::MalformedTimestamp => "The timestamp isn't a number of seconds",
+            AuthError::ExpiredTimestamp => "The timestamp is too far from the time of the runner",
+            AuthError::BodyTooLarge => "The request body is too large",
+            AuthError::InvalidSignature => "The signature doesn't match the request",
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let (status, code) = match self {
+            AuthError::BodyTooLarge => {
+                (StatusCode::PAYLOAD_TOO_LARGE, RunnerErrorCode::BodyTooLarge)
+            }
+            _ => (StatusCode::UNAUTHORIZED, RunnerErrorCode::Unauthorized),
+        };
+        let error = RunnerError {
+            code,
+            message: self.message().to_owned(),
+            retryable: false,
+        };
+        (status, Json(error)).into_response()
+    }
+}
+
+fn header<'a>(parts: &'a Parts, name: &str) -> Option<&'a str> {
+    parts.headers.get(name)?.to_str().ok()
+}
+
+/// Only lets through requests signed by the main server with the shared secret, see
+/// `common::signature`
+pub async fn verify_signature(request: Request, next: Next) -> Result<Response, AuthError> {
+    let (parts, body) = request.into_parts();
+    let (Some(timestamp), Some(signature)) = (
+        header(&parts, TIMESTAMP_HEADER),
+        header(&parts, SIGNATURE_HEADER),
+    ) else {
+        return Err(AuthError::MissingSignature);
+    };
+    let timestamp: u64 = timestamp
+        .parse()
+        .map_err(|_| AuthError::MalformedTimestamp)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The clock is after 1970")
+        .as_secs();
+    if now.abs_diff(timestamp) > *MAX_AGE_SECS {
+        return Err(AuthError::ExpiredTimestamp);
+    }
+
+    let body = to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|_| AuthError::BodyTooLarge)?;
+    let path = parts
+        .uri
+        .path_and_query()
+        .map_or(parts.uri.path(), |path| path.as_str());
+    if !verify(
+        &RUNNER_SECRET,
+        timestamp,
+        parts.method.as_str(),
+        path,
+        &body,
+        signature,
+    ) {
+        return Err(AuthError::InvalidSignature);
+    }
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
@@ -1,4 +1,4 @@
-use std::{hash::Hash, sync::Arc};
+use std::{borrow::Borrow, hash::Hash, sync::Arc};
 
 use dashmap::DashMap;
 use serde::{ser::SerializeMap, Serialize};
@@ -21,6 +21,26 @@ impl<K: Hash + Eq, V> CacheMap<K, V> {
         let entry = self.inner.entry(key).or_default().clone();
         entry
     }
+
+    /// The entry if its value has been initialized. Unlike `get` this never adds an entry.
+    pub fn get_initialized<Q>(&self, key: &Q) -> Option<Arc<OnceCell<V>>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner
+            .get(key)
+            .map(|entry| entry.clone())
+            .filter(|cell| cell.initialized())
+    }
+
+    pub fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.inner.remove(key);
+    }
 }
 
 impl<K: Hash + Eq, V> FromIterator<(K, V)> for CacheMap<K, V> {
@@ -0,0 +1,289 @@
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, ValueEnum};
+use common::{
+    bytes::ByteString, format_transcript, langs::langs, InputFile, ResultDisplay, RunLangOutput,
+    RunPriority, TestCase, TestPassState,
+};
+
+use crate::{
+    run::{get_lang_versions, process_message},
+    Message,
+};
+
+/// The code ran, but didn't pass the judge
+const EXIT_FAILED: u8 = 1;
+/// The code couldn't be run at all
+const EXIT_ERROR: u8 = 2;
+
+/// How long a summary in the table can be
+const SUMMARY_LENGTH: usize = 60;
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// The language of the code, as named in the languages file
+    #[arg(long)]
+    lang: String,
+    /// The version of the language, the latest version by default
+    #[arg(long)]
+    version: Option<String>,
+    /// The file with the code to run
+    #[arg(long)]
+    code: PathBuf,
+    /// The file with the judge
+    #[arg(long)]
+    judge: PathBuf,
+    /// The language the judge is written in. By default the language whose judge runner has
+    /// the same file extension as the judge.
+    #[arg(long)]
+    judge_lang: Option<String>,
+    /// The time limit in seconds, before the language multiplier is applied
+    #[arg(long, default_value_t = 10)]
+    time_limit: u64,
+    /// Run the judge several times to measure how fast the code is
+    #[arg(long)]
+    benchmark: bool,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// The `RunLangOutput` as JSON
+    Json,
+    /// A table of the test cases, with details for the ones that didn't pass
+    Table,
+}
+
+/// Runs code against a judge once, without the HTTP server. Exits with 1 if the code
+/// didn't pass and 2 if it couldn't be run.
+pub async fn run(args: RunArgs) -> ExitCode {
+    match run_once(args).await {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(EXIT_FAILED),
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+async fn run_once(args: RunArgs) -> Result<bool, String> {
+    let code =
+        std::fs::read(&args.code).map_err(|e| format!("Failed to read {:?}: {e}", args.code))?;
+    let judge = std::fs::read_to_string(&args.judge)
+        .map_err(|e| format!("Failed to read {:?}: {e}", args.judge))?;
+
+    let version = {
+        let langs = langs();
+        let lang = langs
+            .get(&args.lang)
+            .ok_or_else(|| format!("Unknown language {}", args.lang))?;
+        args.version.unwrap_or_else(|| lang.latest_version.clone())
+    };
+    let judge_lang = match args.judge_lang {
+        Some(judge_lang) => judge_lang,
+        None => guess_judge_lang(&args.judge).ok_or_else(|| {
+            format!(
+                "No judge runner for {:?}, pass --judge-lang to choose one",
+                args.judge
+            )
+        })?,
+    };
+
+    let message = Message {
+        lang: args.lang,
+        version,
+        code,
+        judge,
+        judge_lang,
+        time_limit: args.time_limit,
+        priority: RunPriority::Interactive,
+        benchmark: args.benchmark,
+    };
+    let lang_versions = get_lang_versions().await;
+    let output = process_message(message, &lang_versions, None)
+        .await
+        .map_err(|e| format!("Run failed: {e:?}"))?;
+
+    match args.format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&output).expect("The output is always valid JSON")
+        ),
+        OutputFormat::Table => print_table(&output),
+    }
+    Ok(output.tests.pass && !output.timed_out && output.limit_exceeded.is_none())
+}
+
+/// The language whose judge runner has the same file extension as the judge
+fn guess_judge_lang(judge: &Path) -> Option<String> {
+    let extension = judge.extension()?;
+    langs()
+        .iter()
+        .find(|(_, lang)| {
+            lang.judge_runner
+                .as_deref()
+                .is_some_and(|runner| Path::new(runner).extension() == Some(extension))
+        })
+        .map(|(name, _)| name.clone())
+}
+
+fn status_label(status: &TestPassState) -> &'static str {
+    match status {
+        TestPassState::Pass => "PASS",
+        TestPassState::Fail => "FAIL",
+        TestPassState::Info => "INFO",
+        TestPassState::Warning => "WARN",
+    }
+}
+
+/// The first line of the most relevant part of a result
+fn summary(display: &ResultDisplay) -> String {
+    let text = match display {
+        ResultDisplay::Empty => String::new(),
+        ResultDisplay::Text(text) => text.clone(),
+        ResultDisplay::Diff { output, .. } | ResultDisplay::Run { output, .. } => {
+            String::from_utf8_lossy(output.as_bytes()).into_owned()
+        }
+        ResultDisplay::Transcript { entries, .. } => format!("{} messages", entries.len()),
+    };
+    let line = text.lines().next().unwrap_or_default();
+    match line.char_indices().nth(SUMMARY_LENGTH) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_owned(),
+    }
+}
+
+fn push_section(output: &mut String, title: &str, content: &ByteString) {
+    if content.as_bytes().is_empty() {
+        return;
+    }
+    let (text, hex) = content.display();
+    output.push_str(title);
+    output.push_str(if hex { " (hex):\n" } else { ":\n" });
+    output.push_str(&text);
+    if !text.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+fn push_program_input(output: &mut String, args: &[String], files: &[InputFile]) {
+    if !args.is_empty() {
+        output.push_str(&format!("Arguments: {args:?}\n"));
+    }
+    for file in files {
+        push_section(output, &file.name, &file.content);
+    }
+}
+
+fn details(display: &ResultDisplay) -> String {
+    let mut output = String::new();
+    match display {
+        ResultDisplay::Empty => {}
+        ResultDisplay::Text(text) => push_section(&mut output, "Text", &text.clone().into()),
+        ResultDisplay::Diff {
+            output: out,
+            expected,
+        } => {
+            push_section(&mut output, "Output", out);
+            push_section(&mut output, "Expected", expected);
+        }
+        ResultDisplay::Run {
+            input,
+            output: out,
+            error,
+            args,
+            files,
+            ..
+        } => {
+            push_program_input(&mut output, args, files);
+            if let Some(input) = input {
+                push_section(&mut output, "Input", input);
+            }
+            push_section(&mut output, "Output", out);
+            push_section(&mut output, "Error", error);
+        }
+        ResultDisplay::Transcript {
+            entries,
+            error,
+            args,
+            files,
+            ..
+        } => {
+            push_program_input(&mut output, args, files);
+            push_section(
+                &mut output,
+                "Transcript",
+                &format_transcript(entries).into(),
+            );
+            push_section(&mut output, "Error", error);
+        }
+    }
+    output
+}
+
+fn test_case_name(index: usize, test_case: &TestCase) -> String {
+    test_case
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("Test case {}", index + 1))
+}
+
+fn print_table(output: &RunLangOutput) {
+    let test_cases = &output.tests.test_cases;
+    let names: Vec<String> = test_cases
+        .iter()
+        .enumerate()
+        .map(|(index, test_case)| test_case_name(index, test_case))
+        .collect();
+    let name_width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .chain(["Test case".len()])
+        .max()
+        .unwrap_or_default();
+
+    println!("Status  {:<name_width$}  Summary", "Test case");
+    for (test_case, name) in test_cases.iter().zip(&names) {
+        println!(
+            "{:<6}  {name:<name_width$}  {}",
+            status_label(&test_case.pass),
+            summary(&test_case.result_display)
+        );
+    }
+
+    for (test_case, name) in test_cases.iter().zip(&names) {
+        if matches!(test_case.pass, TestPassState::Fail | TestPassState::Warning) {
+            println!(
+                "\n--- {name} ({}) ---\n{}",
+                status_label(&test_case.pass),
+                details(&test_case.result_display).trim_end()
+            );
+        }
+    }
+
+    if !output.stderr.is_empty() {
+        println!("\n--- Judge error output ---\n{}", output.stderr);
+    }
+
+    println!();
+    let passed = output.tests.pass && !output.timed_out && output.limit_exceeded.is_none();
+    println!("Result: {}", if passed { "passed" } else { "failed" });
+    if let Some(limit) = output.limit_exceeded {
+        println!("Limit exceeded: {}", limit.description());
+    }
+    if let Some(score) = output.tests.score {
+        println!("Score: {score}");
+    }
+    if let Some(runtime_ms) = output.runtime_ms {
+        println!("Runtime: {runtime_ms:.1} ms");
+    }
+    println!("Usage: {}", output.usage);
+    if output.cached {
+        println!("(cached result)");
+    }
+}
@@ -0,0 +1,12 @@
+use std::str::FromStr;
+
+/// Reads a runner setting from the environment, panicking on invalid values so bad
+/// configuration is caught at startup
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid value for {name}: {value:?}")),
+        Err(_) => default,
+    }
+}
@@ -1,22 +1,23 @@
-use axum::{body::Body, http::Response, response::IntoResponse};
-use serde::{Serialize, Serializer};
-
-fn serialize_error<S: Serializer>(
-    error: &impl ToString,
-    serializer: S,
-) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error> {
-    serializer.serialize_str(&error.to_string())
-}
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use common::{RunnerError, RunnerErrorCode};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub enum RunProcessError {
     NonZeroStatusCode(#[allow(unused)] Option<i32>),
-    SerializationFailed(#[serde(serialize_with = "serialize_error")] serde_json::Error),
-    IOError(
+    SerializationFailed(#[allow(unused)] serde_json::Error),
+    IOError(#[allow(unused)] std::io::Error),
+    /// The sandbox couldn't be set up or started
+    SpawnFailed(#[allow(unused)] std::io::Error),
+    ChecksumMismatch {
+        #[allow(unused)]
+        file: std::path::PathBuf,
+        #[allow(unused)]
+        expected: String,
         #[allow(unused)]
-        #[serde(serialize_with = "serialize_error")]
-        std::io::Error,
-    ),
+        actual: String,
+    },
+    /// The runner is offline and the artifacts directory doesn't have this
+    NotMirrored(#[allow(unused)] String),
 }
 
 impl From<std::io::Error> for RunProcessError {
@@ -25,20 +26,35 @@ impl From<std::io::Error> for RunProcessError {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub enum RunLangError {
-    PluginInstallFailure(#[allow(unused)] RunProcessError),
-    RunLang(#[allow(unused)] RunProcessError),
-    IOError(
+    UnknownLanguage(#[allow(unused)] String),
+    VersionNotAllowed {
+        #[allow(unused)]
+        lang: String,
         #[allow(unused)]
-        #[serde(serialize_with = "serialize_error")]
-        std::io::Error,
-    ),
-    SemaphoreError(
+        version: String,
+    },
+    InvalidVersion(#[allow(unused)] String),
+    /// The toolchain is being installed or uninstalled, or jobs are using it
+    ToolchainBusy {
         #[allow(unused)]
-        #[serde(serialize_with = "serialize_error")]
-        tokio::sync::AcquireError,
-    ),
+        plugin: String,
+        #[allow(unused)]
+        version: String,
+    },
+    NotInstalled {
+        #[allow(unused)]
+        plugin: String,
+        #[allow(unused)]
+        version: String,
+    },
+    UninstallFailure(#[allow(unused)] RunProcessError),
+    PluginInstallFailure(#[allow(unused)] RunProcessError),
+    RunLang(#[allow(unused)] RunProcessError),
+    IOError(#[allow(unused)] std::io::Error),
+    NotAJudgeLanguage(#[allow(unused)] String),
+    SemaphoreError(#[allow(unused)] tokio::sync::oneshot::error::RecvError),
 }
 
 impl From<std::io::Error> for RunLangError {
@@ -47,11 +63,69 @@ impl From<std::io::Error> for RunLangError {
     }
 }
 
+impl RunLangError {
+    fn code(&self) -> RunnerErrorCode {
+        match self {
+            RunLangError::UnknownLanguage(_) => RunnerErrorCode::UnknownLanguage,
+            RunLangError::VersionNotAllowed { .. } => RunnerErrorCode::VersionNotAllowed,
+            RunLangError::InvalidVersion(_) => RunnerErrorCode::InvalidVersion,
+            RunLangError::ToolchainBusy { .. } => RunnerErrorCode::ToolchainBusy,
+            RunLangError::NotInstalled { .. } => RunnerErrorCode::NotInstalled,
+            RunLangError::UninstallFailure(_) => RunnerErrorCode::UninstallFailed,
+            RunLangError::PluginInstallFailure(_) => RunnerErrorCode::InstallFailed,
+            RunLangError::RunLang(RunProcessError::SpawnFailed(_)) => {
+                RunnerErrorCode::SandboxSpawnFailed
+            }
+            RunLangError::RunLang(_) => RunnerErrorCode::Internal,
+            RunLangError::IOError(_) => RunnerErrorCode::Internal,
+            RunLangError::NotAJudgeLanguage(_) => RunnerErrorCode::NotAJudgeLanguage,
+            RunLangError::SemaphoreError(_) => RunnerErrorCode::SemaphoreClosed,
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            RunLangError::UnknownLanguage(_) => StatusCode::NOT_FOUND,
+            RunLangError::VersionNotAllowed { .. } => StatusCode::FORBIDDEN,
+            RunLangError::InvalidVersion(_) => StatusCode::BAD_REQUEST,
+            RunLangError::ToolchainBusy { .. } => StatusCode::CONFLICT,
+            RunLangError::NotInstalled { .. } => StatusCode::NOT_FOUND,
+            RunLangError::NotAJudgeLanguage(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            RunLangError::PluginInstallFailure(_) => StatusCode::BAD_GATEWAY,
+            RunLangError::SemaphoreError(_) => StatusCode::SERVICE_UNAVAILABLE,
+            RunLangError::RunLang(_)
+            | RunLangError::IOError(_)
+            | RunLangError::UninstallFailure(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Errors in the request fail the same way everywhere, the others depend on the runner
+    fn retryable(&self) -> bool {
+        !matches!(
+            self,
+            RunLangError::UnknownLanguage(_)
+                | RunLangError::VersionNotAllowed { .. }
+                | RunLangError::InvalidVersion(_)
+                | RunLangError::NotInstalled { .. }
+                | RunLangError::NotAJudgeLanguage(_)
+                // The same request can't be serialized anywhere
+                | RunLangError::RunLang(RunProcessError::SerializationFailed(_))
+        )
+    }
+}
+
+impl From<RunLangError> for RunnerError {
+    fn from(value: RunLangError) -> Self {
+        RunnerError {
+            code: value.code(),
+            message: format!("{value:?}"),
+            retryable: value.retryable(),
+        }
+    }
+}
+
 impl IntoResponse for RunLangError {
     fn into_response(self) -> axum::response::Response {
-        Response::builder()
-            .status(503)
-            .body(Body::from(format!("{self:?}")))
-            .unwrap()
+        (self.status(), Json(RunnerError::from(self))).into_response()
     }
 }
@@ -0,0 +1,243 @@
+use std::{
+    ffi::CString,
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::PathBuf,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        LazyLock,
+    },
+    time::Duration,
+};
+
+use common::LimitExceeded;
+
+use crate::config::env_or;
+
+/// Resource limits applied to every sandboxed run.
+///
+/// Memory and process count limits are enforced by a cgroup v2 per run when
+/// `YQ_CGROUP_ROOT` points to a cgroup delegated to the runner user. Without it
+/// only rlimits are used, and memory is limited per process with `RLIMIT_DATA` rather
+/// than an address space limit, which breaks V8 since it reserves far more than it uses.
+pub struct Limits {
+    pub memory_bytes: u64,
+    pub max_pids: u64,
+    pub max_file_size_bytes: u64,
+    /// The most CPU time a run may be given, whatever its time limit
+    pub max_cpu_time_secs: u64,
+    cgroup_root: Option<PathBuf>,
+}
+
+pub static LIMITS: LazyLock<Limits> = LazyLock::new(Limits::from_env);
+
+/// Added to the time limit for the CPU time rlimit, so the time limit is what ends slow runs
+const CPU_TIME_SLACK_SECS: u64 = 5;
+
+impl Limits {
+    fn from_env() -> Self {
+        let cgroup_root = std::env::var_os("YQ_CGROUP_ROOT")
+            .map(PathBuf::from)
+            .filter(|root| {
+                // Child cgroups can only use the controllers enabled in the parent
+                match std::fs::write(root.join("cgroup.subtree_control"), "+memory +pids") {
+                    Ok(()) => true,
+                    Err(e) => {
+                        eprintln!(
+                            "Could not enable cgroup controllers in {root:?}, falling back to rlimits: {e}"
+                        );
+                        false
+                    }
+                }
+            });
+
+        Limits {
+            memory_bytes: env_or("YQ_MEMORY_LIMIT", 512 * 1024 * 1024),
+            max_pids: env_or("YQ_PIDS_LIMIT", 256),
+            max_file_size_bytes: env_or("YQ_FILE_SIZE_LIMIT", 64 * 1024 * 1024),
+            max_cpu_time_secs: env_or("YQ_CPU_TIME_LIMIT", 300),
+            cgroup_root,
+        }
+    }
+
+    /// The CPU time rlimit of a run: its time limit plus some slack, since the run is killed
+    /// when it's over its time limit anyway. Capped by `YQ_CPU_TIME_LIMIT`.
+    pub fn cpu_time_secs(&self, time_limit: Duration) -> u64 {
+        (time_limit.as_secs_f64().ceil() as u64 + CPU_TIME_SLACK_SECS).min(self.max_cpu_time_secs)
+    }
+
+    /// Set the rlimits on the command and move it into the cgroup (if any) before it
+    /// executes, so that every process it forks is accounted for.
+    pub fn apply(
+        &self,
+        command: &mut std::process::Command,
+        cgroup: Option<&RunCgroup>,
+        time_limit: Duration,
+    ) {
+        let procs_path = cgroup.map(|cgroup| {
+            CString::new(cgroup.path.join("cgroup.procs").as_os_str().as_bytes())
+                .expect("cgroup paths never contain null bytes")
+        });
+        let cpu_time_secs = self.cpu_time_secs(time_limit);
+        let max_file_size_bytes = self.max_file_size_bytes;
+        // RLIMIT_NPROC counts every process of the runner user, not just this run,
+        // so it's only a last line of defence when there is no cgroup.
+        let max_processes = cgroup.is_none().then_some(self.max_pids);
+        let max_data_bytes = cgroup.is_none().then_some(self.memory_bytes);
+
+        // SAFETY: The closure only performs async-signal-safe syscalls and does not allocate
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(procs_path) = &procs_path {
+                    let fd = libc::open(procs_path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                    if fd < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    let written = libc::write(fd, b"0".as_ptr().cast(), 1);
+                    libc::close(fd);
+                    if written < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+
+                set_rlimit(libc::RLIMIT_CPU, cpu_time_secs)?;
+                set_rlimit(libc::RLIMIT_FSIZE, max_file_size_bytes)?;
+                set_rlimit(libc::RLIMIT_CORE, 0)?;
+                if let Some(max_processes) = max_processes {
+                    set_rlimit(libc::RLIMIT_NPROC, max_processes)?;
+                }
+                if let Some(max_data_bytes) = max_data_bytes {
+                    set_rlimit(libc::RLIMIT_DATA, max_data_bytes)?;
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type RlimitResource = libc::c_int;
+
+fn set_rlimit(resource: RlimitResource, value: u64) -> io::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid rlimit struct that outlives the call
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Works out which rlimit killed the process from its exit status. Bubblewrap
+/// reports a child killed by a signal as exit code 128 + signal.
+pub fn limit_from_exit_status(status: ExitStatus) -> Option<LimitExceeded> {
+    let signal = status.signal().or_else(|| {
+        status
+            .code()
+            .filter(|&code| code > 128)
+            .map(|code| code - 128)
+    })?;
+
+    match signal {
+        libc::SIGXCPU => Some(LimitExceeded::CpuTimeExceeded),
+        libc::SIGXFSZ => Some(LimitExceeded::FileSizeExceeded),
+        _ => None,
+    }
+}
+
+/// A cgroup that holds every process of a single run
+pub struct RunCgroup {
+    path: PathBuf,
+}
+
+impl RunCgroup {
+    pub fn create(limits: &Limits) -> io::Result<Option<RunCgroup>> {
+        static RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let Some(root) = &limits.cgroup_root else {
+            return Ok(None);
+        };
+
+        let path = root.join(format!(
+            "run-{}",
+            RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir(&path)?;
+        let cgroup = RunCgroup { path };
+
+        cgroup.write("memory.max", limits.memory_bytes)?;
+        cgroup.write("pids.max", limits.max_pids)?;
+        // Only exists if the kernel has swap accounting enabled
+        match cgroup.write("memory.swap.max", 0) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            other => other?,
+        }
+
+        Ok(Some(cgroup))
+    }
+
+    fn write(&self, file: &str, value: u64) -> io::Result<()> {
+        std::fs::write(self.path.join(file), value.to_string())
+    }
+
+    /// Reads a value from a flat keyed file like `memory.events` or `cpu.stat`
+    fn read_key(&self, file: &str, key: &str) -> Option<u64> {
+        std::fs::read_to_string(self.path.join(file))
+            .ok()?
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .find(|(name, _)| *name == key)
+            .and_then(|(_, value)| value.trim().parse().ok())
+    }
+
+    fn read_event(&self, file: &str, event: &str) -> u64 {
+        self.read_key(file, event).unwrap_or(0)
+    }
+
+    /// CPU time used by every process that ran in the cgroup
+    pub fn cpu_time(&self) -> Option<Duration> {
+        self.read_key("cpu.stat", "usage_usec")
+            .map(Duration::from_micros)
+    }
+
+    /// The most memory the cgroup used at once. `memory.peak` only exists since Linux 5.19.
+    pub fn peak_memory_bytes(&self) -> Option<u64> {
+        std::fs::read_to_string(self.path.join("memory.peak"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    pub fn limit_exceeded(&self) -> Option<LimitExceeded> {
+        if self.read_event("memory.events", "oom_kill") > 0 {
+            Some(LimitExceeded::MemoryExceeded)
+        } else if self.read_event("pids.events", "max") > 0 {
+            Some(LimitExceeded::PidsExceeded)
+        } else {
+            None
+        }
+    }
+
+    /// Kills anything still left in the cgroup and removes it
+    pub async fn remove(self) {
+        let _ = std::fs::write(self.path.join("cgroup.kill"), "1");
+        for _ in 0..50 {
+            match std::fs::remove_dir(&self.path) {
+                Ok(()) => return,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+                // The killed processes take a moment to exit
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+        eprintln!("Failed to remove cgroup {:?}", self.path);
+    }
+}
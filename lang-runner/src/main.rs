@@ -1,15 +1,34 @@
+mod artifacts;
+mod auth;
 mod cachemap;
+mod cli;
+mod config;
 mod error;
+mod limits;
 mod parse_output;
+mod result_cache;
 mod run;
+mod sandbox;
+mod scheduler;
+mod toolchains;
 
-use std::sync::Arc;
+use std::{process::ExitCode, sync::Arc};
 
-use axum::{extract::State, routing::get, Json, Router};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, Response, StatusCode},
+    middleware,
+    routing::{get, post},
+    Json, Router,
+};
 use cachemap::CacheMap;
-use common::RunLangOutput;
+use clap::{Parser, Subcommand};
+use common::{langs, RunLangEvent, RunLangOutput, RunPriority};
 use error::RunLangError;
-use run::{get_lang_versions, process_message};
+use futures_util::StreamExt;
+use run::{get_lang_versions, install_version, process_message};
+use scheduler::{QueueStatus, SCHEDULER};
 use serde::{Deserialize, Serialize};
 use tokio::signal;
 
@@ -17,24 +36,104 @@ use tokio::signal;
 pub struct Message {
     lang: String,
     version: String,
-    code: String,
+    /// Base64 in JSON, code doesn't have to be valid UTF-8
+    #[serde(with = "common::bytes::base64")]
+    code: Vec<u8>,
     judge: String,
+    /// The language the judge is written in, it must have a judge runner
+    #[serde(default = "default_judge_lang")]
+    judge_lang: String,
+    /// The time limit of the challenge in seconds, before the language multiplier is applied
+    time_limit: u64,
+    #[serde(default)]
+    priority: RunPriority,
+    /// Run the judge several times to measure how fast the code is. Benchmarks are never
+    /// cached.
+    #[serde(default)]
+    benchmark: bool,
 }
 
-#[tokio::main]
-async fn main() {
+fn default_judge_lang() -> String {
+    "deno".to_owned()
+}
+
+#[derive(Parser)]
+#[command(about = "Runs code against judges in a sandbox")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Start the HTTP server, this is the default
+    Serve,
+    /// Run code against a judge once and print the result
+    Run(cli::RunArgs),
+}
+
+fn main() -> ExitCode {
+    let command = Cli::parse().command.unwrap_or(CliCommand::Serve);
+    if let CliCommand::Serve = command {
+        // Reading the secret removes it from the environment, which is only safe while no
+        // other thread can read the environment, so before the runtime starts any
+        std::sync::LazyLock::force(&auth::RUNNER_SECRET);
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the tokio runtime");
+    runtime.block_on(async {
+        match command {
+            CliCommand::Serve => {
+                serve().await;
+                ExitCode::SUCCESS
+            }
+            CliCommand::Run(args) => cli::run(args).await,
+        }
+    })
+}
+
+async fn serve() {
     println!("Starting server");
     // initialize tracing
     tracing_subscriber::fmt::init();
 
-    let lang_versions = get_lang_versions().await;
+    // Surface bad configuration at startup rather than on the first run
+    std::sync::LazyLock::force(&limits::LIMITS);
+    std::sync::LazyLock::force(&SCHEDULER);
+    std::sync::LazyLock::force(&result_cache::RESULT_CACHE);
+    std::sync::LazyLock::force(&run::BENCHMARK_RUNS);
+    std::sync::LazyLock::force(&sandbox::SANDBOX);
+    std::sync::LazyLock::force(&artifacts::OFFLINE);
+    langs::langs();
+
+    let lang_versions = Arc::new(get_lang_versions().await);
+    toolchains::write_lockfile();
+    toolchains::prewarm(lang_versions.clone());
 
     // build our application with a route
     let app = Router::new()
         // `GET /` goes to `root`
         .route("/", get(root).post(handle_message))
+        .route("/stream", post(handle_message_stream))
         .route("/lang-versions", get(lang_versions_endpoint))
-        .with_state(Arc::new(lang_versions));
+        .route("/queue", get(queue_status))
+        .route("/reload-langs", post(reload_langs))
+        .route("/install", post(install_version_endpoint))
+        .route("/toolchains", get(toolchains::list_toolchains))
+        .route("/toolchains/health", get(toolchains::check_toolchains))
+        .route("/toolchains/gc", post(toolchains::collect_garbage))
+        .route(
+            "/toolchains/:lang/:version",
+            get(toolchains::toolchain_status)
+                .post(toolchains::start_install)
+                .delete(toolchains::uninstall),
+        )
+        // Every request must be signed by the main server
+        .layer(middleware::from_fn(auth::verify_signature))
+        .with_state(lang_versions);
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -56,15 +155,70 @@ async fn lang_versions_endpoint(
     Json(serde_json::to_value(&*lang_versions).unwrap())
 }
 
+async fn queue_status() -> Json<QueueStatus> {
+    Json(SCHEDULER.status())
+}
+
+/// Reads the languages file again, so languages can be added or changed without a restart
+async fn reload_langs() -> Result<(), (StatusCode, String)> {
+    langs::reload_langs().map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+    // Cached results may have been produced by a different run command
+    result_cache::RESULT_CACHE.clear();
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct InstallRequest {
+    lang: String,
+    version: String,
+}
+
+/// Installs a version that isn't allowed by the languages file. Only the main server can sign
+/// requests, and it only sends these for admins.
+async fn install_version_endpoint(
+    State(lang_versions): State<Arc<CacheMap<String, CacheMap<String, ()>>>>,
+    Json(request): Json<InstallRequest>,
+) -> Result<(), RunLangError> {
+    install_version(&request.lang, &request.version, &lang_versions).await
+}
+
 #[axum::debug_handler]
 async fn handle_message(
     lang_versions: State<Arc<CacheMap<String, CacheMap<String, ()>>>>,
     message: Json<Message>,
 ) -> Result<Json<RunLangOutput>, RunLangError> {
-    let result = process_message(message.0, &lang_versions.0).await?;
+    let result = process_message(message.0, &lang_versions.0, None).await?;
     Ok(Json(result))
 }
 
+/// Same as `handle_message`, but responds with newline delimited JSON `RunLangEvent`s
+/// so test cases can be shown while the judge is still running.
+async fn handle_message_stream(
+    State(lang_versions): State<Arc<CacheMap<String, CacheMap<String, ()>>>>,
+    Json(message): Json<Message>,
+) -> Response<Body> {
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let event = match process_message(message, &lang_versions, Some(sender.clone())).await {
+            Ok(output) => RunLangEvent::Done(output),
+            Err(e) => RunLangEvent::Error(e.into()),
+        };
+        let _ = sender.send(event);
+    });
+
+    let lines = futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx)).map(|event| {
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        Ok::<_, serde_json::Error>(line)
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(lines))
+        .unwrap()
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -1,10 +1,25 @@
-use common::{JudgeResult, TestCase};
+use common::{JudgeResult, RunLangEvent, TestCase};
 use futures_util::AsyncReadExt;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
 
 #[derive(Serialize, Deserialize)]
 pub struct FinalVerdict {
     pass: bool,
+    #[serde(default)]
+    deterministic: bool,
+    #[serde(default)]
+    score: Option<f64>,
+}
+
+/// Requests the judge runner makes while it runs, as opposed to results
+#[derive(Deserialize)]
+pub enum JudgeRequest {
+    /// Asks for a slot to run one more program concurrently. Answered with `true` or `false`
+    /// on a line of the judge runner's stdin.
+    AcquireSlot,
+    /// Gives back a slot that was granted earlier
+    ReleaseSlot,
 }
 
 const MAX_TEST_CASES: usize = 50;
@@ -27,7 +42,15 @@ pub fn apply_to_judge_result(judge_result: &mut JudgeResult, line: &[u8]) {
         Err(e) => {
             eprintln!("{e:#?}");
             match serde_json::from_slice::<FinalVerdict>(line) {
-                Ok(FinalVerdict { pass: new_pass }) => judge_result.pass = new_pass,
+                Ok(FinalVerdict {
+                    pass: new_pass,
+                    deterministic,
+                    score,
+                }) => {
+                    judge_result.pass = new_pass;
+                    judge_result.deterministic = deterministic;
+                    judge_result.score = score;
+                }
                 Err(_e) => judge_result.test_cases.push(TestCase {
                     name: Some("Judge Debug Message".to_owned()),
                     pass: common::TestPassState::Info,
@@ -40,10 +63,18 @@ pub fn apply_to_judge_result(judge_result: &mut JudgeResult, line: &[u8]) {
     }
 }
 
-pub async fn parse_judge_result_from_stream(mut stream: impl AsyncReadExt + Unpin) -> JudgeResult {
+/// Parses the judge output. Every test case is also sent to `events` as soon as it's parsed,
+/// and requests of the judge runner are sent to `requests`.
+pub async fn parse_judge_result_from_stream(
+    mut stream: impl AsyncReadExt + Unpin,
+    events: Option<UnboundedSender<RunLangEvent>>,
+    requests: UnboundedSender<JudgeRequest>,
+) -> JudgeResult {
     let mut judge_result = JudgeResult {
         test_cases: vec![],
         pass: false,
+        deterministic: false,
+        score: None,
     };
 
     let mut line_buffer = vec![];
@@ -58,9 +89,25 @@ pub async fn parse_judge_result_from_stream(mut stream: impl AsyncReadExt + Unpi
         while let Some(i) = part.iter().position(|&d| d == b'\n') {
             line_buffer.extend_from_slice(&part[..i]);
 
+            if let Ok(request) = serde_json::from_slice::<JudgeRequest>(&line_buffer) {
+                // Nobody answers once the judge is done
+                let _ = requests.send(request);
+                line_buffer.clear();
+                part = &part[i + 1..];
+                continue;
+            }
+
+            let previous_test_cases = judge_result.test_cases.len();
             apply_to_judge_result(&mut judge_result, &line_buffer);
             line_buffer.clear();
 
+            if let Some(events) = &events {
+                for test_case in &judge_result.test_cases[previous_test_cases..] {
+                    // The receiver hanging up shouldn't stop the run
+                    let _ = events.send(RunLangEvent::TestCase(test_case.clone()));
+                }
+            }
+
             part = &part[i + 1..];
         }
         line_buffer.extend_from_slice(part);
@@ -0,0 +1,94 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+};
+
+use common::RunLangOutput;
+use sha2::{Digest, Sha256};
+
+use crate::{config::env_or, Message};
+
+/// Remembers the output of deterministic runs by a hash of their inputs, so identical
+/// submissions (mostly from solution invalidation) don't have to be run again.
+///
+/// Disabled unless `YQ_RESULT_CACHE_SIZE` is set. The cache only lives in memory, so
+/// a new deploy of the runner script starts with an empty cache.
+pub struct ResultCache {
+    max_entries: usize,
+    state: Mutex<ResultCacheState>,
+}
+
+#[derive(Default)]
+struct ResultCacheState {
+    outputs: HashMap<String, RunLangOutput>,
+    /// Hashes in insertion order, the oldest one is evicted first
+    order: VecDeque<String>,
+}
+
+pub static RESULT_CACHE: LazyLock<ResultCache> = LazyLock::new(ResultCache::from_env);
+
+/// Hashes everything that can affect the result of a run
+pub fn input_hash(message: &Message, judge_lang: &str, judge_version: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in [
+        message.lang.as_bytes(),
+        message.version.as_bytes(),
+        &message.code,
+        message.judge.as_bytes(),
+        &message.time_limit.to_le_bytes(),
+        judge_lang.as_bytes(),
+        judge_version.as_bytes(),
+    ] {
+        // Length prefixed so moving bytes between fields changes the hash
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hex::encode(hasher.finalize())
+}
+
+impl ResultCache {
+    fn from_env() -> Self {
+        ResultCache {
+            max_entries: env_or("YQ_RESULT_CACHE_SIZE", 0),
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn get(&self, input_hash: &str) -> Option<RunLangOutput> {
+        let state = self.state.lock().unwrap();
+        let mut output = state.outputs.get(input_hash)?.clone();
+        output.cached = true;
+        Some(output)
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.outputs.clear();
+        state.order.clear();
+    }
+
+    /// Stores the output if the judge declared it deterministic and no limit was hit,
+    /// since running out of time or memory depends on the load of the machine.
+    pub fn insert(&self, output: &RunLangOutput) {
+        if self.max_entries == 0 || !output.tests.deterministic || output.limit_exceeded.is_some() {
+            return;
+        }
+        let Some(input_hash) = &output.input_hash else {
+            return;
+        };
+
+        let mut state = self.state.lock().unwrap();
+        if state
+            .outputs
+            .insert(input_hash.clone(), output.clone())
+            .is_none()
+        {
+            state.order.push_back(input_hash.clone());
+        }
+        while state.order.len() > self.max_entries {
+            if let Some(oldest) = state.order.pop_front() {
+                state.outputs.remove(&oldest);
+            }
+        }
+    }
+}
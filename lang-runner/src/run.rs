@@ -1,29 +1,64 @@
-use std::{path::PathBuf, process::Stdio, time::Duration};
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 
-use async_process::Command;
+use async_process::{ChildStdin, Command};
 use common::{
-    langs::{Lang, LANGS},
-    RunLangOutput,
+    langs::{is_valid_version, langs, Lang},
+    JudgeResult, LimitExceeded, ResourceUsage, ResultDisplay, RunLangEvent, RunLangOutput,
 };
 use futures_util::AsyncWriteExt;
 use serde::Serialize;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 use crate::{
+    artifacts::plugin_source,
     cachemap::CacheMap,
+    config::env_or,
     error::{RunLangError, RunProcessError},
-    parse_output::parse_judge_result_from_stream,
+    limits::{limit_from_exit_status, RunCgroup, LIMITS},
+    parse_output::{parse_judge_result_from_stream, JudgeRequest},
+    result_cache::{input_hash, RESULT_CACHE},
+    sandbox::{Mount, SandboxSpec, SandboxedCommand, SANDBOX},
+    scheduler::SCHEDULER,
+    toolchains::{install_toolchain, register_installed, start_job},
     Message,
 };
 
-const MAX_CONCURRENT_RUNS: usize = 4;
+/// How often the judge runs for a benchmark, read from `YQ_BENCHMARK_RUNS`
+pub static BENCHMARK_RUNS: LazyLock<usize> =
+    LazyLock::new(|| env_or("YQ_BENCHMARK_RUNS", 3).max(1));
+
+/// Host files and directories every judge can read, from `YQ_SANDBOX_SYSTEM_PATHS` separated by
+/// `:`. By default only what dynamically linked programs need, languages add what else they need
+/// with `sandboxPaths`. Paths that don't exist are left out, so the defaults work outside the
+/// Docker image.
+static SYSTEM_PATHS: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
+    std::env::var("YQ_SANDBOX_SYSTEM_PATHS")
+        .unwrap_or_else(|_| {
+            "/lib:/lib64:/usr/lib:/usr/lib64:/etc/ld.so.cache:/etc/ld.so.conf:/etc/ld.so.conf.d"
+                .to_owned()
+        })
+        .split(':')
+        .map(PathBuf::from)
+        .filter(|path| path.exists())
+        .collect()
+});
 
-static RUNS_SEMAPHORE: tokio::sync::Semaphore =
-    tokio::sync::Semaphore::const_new(MAX_CONCURRENT_RUNS);
+/// The judge runner scripts on the host, from `YQ_SCRIPTS_DIR`. Judges see them in `/scripts`.
+static SCRIPTS_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    std::env::var_os("YQ_SCRIPTS_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/scripts"))
+});
 
 async fn install_plugin(lang: &Lang) -> Result<CacheMap<String, ()>, RunProcessError> {
-    println!("Installing language version {}", lang.display_name);
+    eprintln!("Installing language version {}", lang.display_name);
     let plugin_install_output = Command::new("asdf")
-        .args(["plugin", "add", lang.plugin_name, lang.plugin])
+        .args(["plugin", "add", &lang.plugin_name, &plugin_source(lang)?])
         .stderr(Stdio::inherit())
         .status()
         .await?;
@@ -36,30 +71,19 @@ async fn install_plugin(lang: &Lang) -> Result<CacheMap<String, ()>, RunProcessE
 }
 
 async fn install_language_version(lang: &Lang, version: &str) -> Result<(), RunProcessError> {
-    println!(
+    eprintln!(
         "Installing language version {} {}",
         lang.display_name, version
     );
-    let status = Command::new("asdf")
-        .args(["install", lang.plugin_name, version])
-        .stderr(Stdio::inherit())
-        .status()
-        .await?;
-
-    if !status.success() {
-        return Err(RunProcessError::NonZeroStatusCode(status.code()));
-    }
-    Ok(())
+    install_toolchain(lang, version).await
 }
 
-async fn install_lang(
-    lang_name: String,
+pub async fn install_lang(
+    lang: &Lang,
     version: &str,
     versions: &CacheMap<String, CacheMap<String, ()>>,
 ) -> Result<(), RunProcessError> {
-    let lang = LANGS.get(&lang_name).unwrap();
-
-    let lang_version_token = versions.get(lang.plugin_name.to_owned());
+    let lang_version_token = versions.get(lang.plugin_name.clone());
     let lang_versions = lang_version_token
         .get_or_try_init(|| install_plugin(lang))
         .await?;
@@ -73,9 +97,45 @@ async fn install_lang(
     Ok(())
 }
 
-async fn get_lang_directory(lang: &Lang, version: &str) -> Result<PathBuf, RunProcessError> {
+fn is_installed(
+    lang: &Lang,
+    version: &str,
+    versions: &CacheMap<String, CacheMap<String, ()>>,
+) -> bool {
+    versions
+        .get_initialized(&lang.plugin_name)
+        .is_some_and(|plugin| {
+            plugin
+                .get()
+                .is_some_and(|installed| installed.get_initialized(version).is_some())
+        })
+}
+
+/// Installs a version of a language even if it isn't allowed, so solutions can use it from then
+/// on. Only admins can do this through the main server.
+pub async fn install_version(
+    lang_name: &str,
+    version: &str,
+    lang_versions: &CacheMap<String, CacheMap<String, ()>>,
+) -> Result<(), RunLangError> {
+    let langs = langs();
+    let lang = langs
+        .get(lang_name)
+        .ok_or_else(|| RunLangError::UnknownLanguage(lang_name.to_owned()))?;
+    if !is_valid_version(version) {
+        return Err(RunLangError::InvalidVersion(version.to_owned()));
+    }
+    install_lang(lang, version, lang_versions)
+        .await
+        .map_err(RunLangError::PluginInstallFailure)
+}
+
+pub async fn get_lang_directory(
+    plugin_name: &str,
+    version: &str,
+) -> Result<PathBuf, RunProcessError> {
     let lang_folder = Command::new("asdf")
-        .args(["where", lang.plugin_name, version])
+        .args(["where", plugin_name, version])
         .stderr(Stdio::inherit())
         .output()
         .await?;
@@ -90,120 +150,173 @@ async fn get_lang_directory(lang: &Lang, version: &str) -> Result<PathBuf, RunPr
 }
 
 async fn run_lang(
-    lang_name: &str,
-    version: &str,
-    code: &str,
-    judge: &str,
-    judge_lang: &str,
+    message: &Message,
+    lang: &Lang,
+    judge_lang: &Lang,
     judge_version: &str,
+    events: Option<UnboundedSender<RunLangEvent>>,
 ) -> Result<RunLangOutput, RunProcessError> {
-    let lang = LANGS.get(lang_name).unwrap();
-    let judge_lang = LANGS.get(judge_lang).unwrap();
-
-    let code_lang_folder = get_lang_directory(lang, version).await?;
-    let judge_lang_folder = get_lang_directory(judge_lang, judge_version).await?;
+    let code_lang_folder = get_lang_directory(&lang.plugin_name, &message.version).await?;
+    let judge_lang_folder = get_lang_directory(&judge_lang.plugin_name, judge_version).await?;
+
+    let cgroup = RunCgroup::create(&LIMITS)?;
+    let output = run_sandboxed(
+        message,
+        lang,
+        judge_lang,
+        code_lang_folder,
+        judge_lang_folder,
+        cgroup.as_ref(),
+        events,
+    )
+    .await;
+    if let Some(cgroup) = cgroup {
+        cgroup.remove().await;
+    }
+    output
+}
 
-    let mut command = Command::new("bwrap");
-    command
-        .args([
-            "--die-with-parent",
-            // "--ro-bind",
-            // "/proc/self",
-            // "/proc/self",
-            "--ro-bind",
-            "/bin",
-            "/bin",
-            "--chdir",
-            "/",
-            "--ro-bind",
-            "/lib64",
-            "/lib64",
-            "--ro-bind",
-            "/usr",
-            "/usr",
-            "--ro-bind",
-            "/lib",
-            "/lib",
-            "--ro-bind",
-            "/etc",
-            "/etc",
-            "--ro-bind",
-            "/etc/alternatives",
-            "/etc/alternatives",
-            "--tmpfs",
-            "/tmp",
-            "--tmpfs",
-            "/home/yq",
-            "--setenv",
-            "HOME",
-            "/home/yq",
-        ])
-        .args(["--ro-bind"])
-        .arg(code_lang_folder)
-        .args(["/lang"])
-        .args(["--ro-bind"])
-        .arg(judge_lang_folder)
-        .arg("/judge")
-        .args(["--ro-bind", "/scripts", "/scripts"])
-        .args(["--unshare-all", "--new-session"]);
-
-    for (key, value) in judge_lang.env {
-        command.args(["--setenv", *key, *value]);
+async fn run_sandboxed(
+    message: &Message,
+    lang: &Lang,
+    judge_lang: &Lang,
+    code_lang_folder: PathBuf,
+    judge_lang_folder: PathBuf,
+    cgroup: Option<&RunCgroup>,
+    events: Option<UnboundedSender<RunLangEvent>>,
+) -> Result<RunLangOutput, RunProcessError> {
+    // Both languages run in the sandbox, the judge runs the code
+    let mut paths: Vec<PathBuf> = SYSTEM_PATHS.clone();
+    for path in lang.sandbox_paths.iter().chain(&judge_lang.sandbox_paths) {
+        let path = PathBuf::from(path);
+        if path.exists() && !paths.contains(&path) {
+            paths.push(path);
+        }
     }
+    let mut mounts: Vec<Mount> = paths
+        .iter()
+        .map(|path| Mount::read_only(path, path))
+        .collect();
+    mounts.extend([
+        Mount::tmpfs("/tmp"),
+        Mount::tmpfs("/home/yq"),
+        // Judge runners read the CPU time of the programs they run from /proc/self/stat
+        Mount::proc("/proc"),
+        Mount::read_only(code_lang_folder, "/lang"),
+        Mount::read_only(judge_lang_folder, "/judge"),
+        Mount::read_only(&*SCRIPTS_DIR, "/scripts"),
+    ]);
+
+    let judge_runner = format!(
+        "/scripts/{}",
+        judge_lang
+            .judge_runner
+            .as_deref()
+            .expect("Checked by process_message")
+    );
+    let replace_placeholders = |value: &str| {
+        value
+            .replace("${LANG_LOCATION}", "/judge")
+            .replace("${FILE_LOCATION}", &judge_runner)
+    };
+
+    // Judge runners find the language of the code and their temporary directory through
+    // these, so sandboxes that can't mount them in the usual place still work
+    let mut env: Vec<(String, String)> = [
+        ("HOME", "/home/yq"),
+        ("TMPDIR", "/tmp"),
+        ("YQ_LANG_LOCATION", "/lang"),
+    ]
+    .map(|(key, value)| (key.to_owned(), value.to_owned()))
+    .into();
+    env.extend(
+        judge_lang
+            .env
+            .iter()
+            .map(|(key, value)| (key.clone(), replace_placeholders(value))),
+    );
 
+    let spec = SandboxSpec {
+        mounts,
+        env,
+        cwd: PathBuf::from("/"),
+        command: judge_lang
+            .run_command
+            .iter()
+            .map(|k| replace_placeholders(k))
+            .collect(),
+    };
+    // The temporary directories must outlive the judge
+    let SandboxedCommand {
+        mut command,
+        temp_dirs: _temp_dirs,
+        inherited_files: _inherited_files,
+    } = SANDBOX
+        .command(&spec)
+        .map_err(RunProcessError::SpawnFailed)?;
+    let time_limit = Duration::from_secs(message.time_limit).mul_f32(lang.time_limit_multiplier);
+    LIMITS.apply(&mut command, cgroup, time_limit);
+
+    let mut command = Command::from(command);
     command
-        .args(
-            judge_lang
-                .run_command
-                .iter()
-                .map(|k| {
-                    k.replace("${LANG_LOCATION}", "/judge")
-                        .replace("${FILE_LOCATION}", "/scripts/runner.ts")
-                })
-                .collect::<Vec<_>>(),
-        )
         .stdout(Stdio::piped())
         .stdin(Stdio::piped())
         .stderr(Stdio::piped());
 
     // .args([&format!("/lang/{}", lang.bin_location), code as &str, judge]);
 
-    let mut child = command.spawn()?;
-    let Some(stdin) = &mut child.stdin else {
-        panic!("Child stdin should exist");
-    };
+    let start_time = Instant::now();
+    let mut child = command.spawn().map_err(RunProcessError::SpawnFailed)?;
+    let mut stdin = child.stdin.take().expect("Child stdin should exist");
 
     #[derive(Serialize)]
     struct RunnerInput<'a> {
         lang: &'a Lang,
-        code: &'a str,
+        #[serde(with = "common::bytes::base64")]
+        code: &'a [u8],
         judge: &'a str,
     }
 
-    let data = serde_json::to_string(&RunnerInput { lang, code, judge })
-        .map_err(RunProcessError::SerializationFailed)?;
+    let data = serde_json::to_string(&RunnerInput {
+        lang,
+        code: &message.code,
+        judge: &message.judge,
+    })
+    .map_err(RunProcessError::SerializationFailed)?;
+    // The input is one line, stdin stays open for the answers to the judge runner's requests
     stdin.write_all(data.as_bytes()).await?;
-    stdin.close().await?;
-
+    stdin.write_all(b"\n").await?;
+    stdin.flush().await?;
+
+    let (requests, request_receiver) = unbounded_channel();
+    // Benchmarked programs run one at a time, so their wall times add up to the runtime
+    tokio::spawn(answer_judge_requests(
+        stdin,
+        request_receiver,
+        !message.benchmark,
+    ));
     let judge_result = tokio::spawn(parse_judge_result_from_stream(
         child
             .stdout
             .take()
             .expect("The child stdout is already consumed"),
+        events,
+        requests,
     ));
     let id = child.id();
 
-    let timed_out = tokio::select! {
-        _status = child.status() => {
-            println!("Child finished normally {id}");
-            false
+    let (timed_out, status) = tokio::select! {
+        status = child.status() => {
+            eprintln!("Child finished normally {id}");
+            (false, Some(status?))
         }
-        _timeout = tokio::time::sleep(Duration::from_secs(3)) => {
+        _timeout = tokio::time::sleep(time_limit) => {
             child.kill().unwrap();
             eprintln!("Timed out {id}");
-            true
+            (true, None)
         }
     };
+    let wall_time = start_time.elapsed();
     eprintln!("Awaiting output");
     let output = child.output().await?;
 
@@ -212,42 +325,175 @@ async fn run_lang(
     let mut stderr = output.stderr;
     stderr.truncate(1000);
 
+    let limit_exceeded = cgroup
+        .and_then(RunCgroup::limit_exceeded)
+        .or_else(|| status.and_then(limit_from_exit_status))
+        .or(timed_out.then_some(LimitExceeded::TimedOut));
+
     Ok(RunLangOutput {
         stderr: String::from_utf8_lossy(&stderr).into_owned(),
         tests: judge_result.await.unwrap(),
         timed_out,
+        limit_exceeded,
+        input_hash: None,
+        cached: false,
+        runtime_ms: None,
+        usage: ResourceUsage {
+            wall_time_ms: wall_time.as_secs_f64() * 1000.0,
+            cpu_time_ms: cgroup
+                .and_then(RunCgroup::cpu_time)
+                .map(|time| time.as_secs_f64() * 1000.0),
+            peak_memory_kib: cgroup
+                .and_then(RunCgroup::peak_memory_bytes)
+                .map(|bytes| bytes / 1024),
+        },
     })
 }
 
+/// Hands out slots of the scheduler to the judge runner, so programs it runs concurrently
+/// count against the runner-wide limit. The judge always has the slot of its own run, so
+/// extra slots are never waited for. They are all released once the judge exits. Without
+/// `extra_slots` every request is refused, and the programs run one at a time.
+async fn answer_judge_requests(
+    mut stdin: ChildStdin,
+    mut requests: UnboundedReceiver<JudgeRequest>,
+    extra_slots: bool,
+) {
+    let mut permits = vec![];
+    while let Some(request) = requests.recv().await {
+        match request {
+            JudgeRequest::AcquireSlot => {
+                let permit = extra_slots.then(|| SCHEDULER.try_acquire()).flatten();
+                let reply: &[u8] = if permit.is_some() {
+                    b"true\n"
+                } else {
+                    b"false\n"
+                };
+                permits.extend(permit);
+                if stdin.write_all(reply).await.is_err() || stdin.flush().await.is_err() {
+                    break;
+                }
+            }
+            JudgeRequest::ReleaseSlot => {
+                permits.pop();
+            }
+        }
+    }
+}
+
+/// The wall time spent running the submitted programs, leaving out the judge itself. `None`
+/// when the judge ran no program, or one whose time wasn't measured.
+fn program_time_ms(result: &JudgeResult) -> Option<f64> {
+    let mut total = None;
+    for test_case in &result.test_cases {
+        let usage = match &test_case.result_display {
+            ResultDisplay::Run { usage, .. } | ResultDisplay::Transcript { usage, .. } => {
+                usage.as_ref()?
+            }
+            _ => continue,
+        };
+        *total.get_or_insert(0.0) += usage.wall_time_ms;
+    }
+    total
+}
+
+/// Runs the judge `BENCHMARK_RUNS` times and keeps the fastest run, since noise only ever
+/// makes code slower. Stops at the first run that fails.
+async fn run_benchmark(
+    message: &Message,
+    lang: &Lang,
+    judge_lang: &Lang,
+    judge_version: &str,
+    mut events: Option<UnboundedSender<RunLangEvent>>,
+) -> Result<RunLangOutput, RunProcessError> {
+    let mut fastest: Option<RunLangOutput> = None;
+    for _ in 0..*BENCHMARK_RUNS {
+        // Only the first run is streamed, the rest would show the same test cases again
+        let mut output = run_lang(message, lang, judge_lang, judge_version, events.take()).await?;
+        if !output.tests.pass || output.limit_exceeded.is_some() {
+            return Ok(output);
+        }
+
+        // Without a runtime there's nothing to compare, the first run is as good as any
+        output.runtime_ms = program_time_ms(&output.tests);
+        let faster = match (&fastest, output.runtime_ms) {
+            (None, _) => true,
+            (Some(fastest), Some(runtime_ms)) => fastest.runtime_ms.is_some_and(|t| runtime_ms < t),
+            (Some(_), None) => false,
+        };
+        if faster {
+            fastest = Some(output);
+        }
+    }
+    Ok(fastest.expect("BENCHMARK_RUNS is at least 1"))
+}
+
 pub async fn process_message(
     message: Message,
     lang_versions: &CacheMap<String, CacheMap<String, ()>>,
+    events: Option<UnboundedSender<RunLangEvent>>,
 ) -> Result<RunLangOutput, RunLangError> {
-    let deno_latest_version = LANGS.get("deno").unwrap().latest_version;
+    let langs = langs();
+    let Some(lang) = langs.get(&message.lang) else {
+        return Err(RunLangError::UnknownLanguage(message.lang));
+    };
+    let judge_lang = match langs.get(&message.judge_lang) {
+        Some(judge_lang) if judge_lang.judge_runner.is_some() => judge_lang,
+        _ => return Err(RunLangError::NotAJudgeLanguage(message.judge_lang)),
+    };
+    let judge_version = judge_lang.latest_version.clone();
+    // Only the allowed versions are installed on demand, others must be installed by an admin
+    if !lang.allows_version(&message.version)
+        && !is_installed(lang, &message.version, lang_versions)
+    {
+        return Err(RunLangError::VersionNotAllowed {
+            lang: message.lang,
+            version: message.version,
+        });
+    }
+
+    let input_hash = input_hash(&message, &message.judge_lang, &judge_version);
+    if let Some(output) = RESULT_CACHE.get(&input_hash).filter(|_| !message.benchmark) {
+        if let Some(events) = &events {
+            for test_case in &output.tests.test_cases {
+                let _ = events.send(RunLangEvent::TestCase(test_case.clone()));
+            }
+        }
+        return Ok(output);
+    }
 
     // Runner Lang
-    install_lang("deno".to_owned(), deno_latest_version, lang_versions)
+    install_lang(judge_lang, &judge_version, lang_versions)
         .await
         .map_err(RunLangError::PluginInstallFailure)?;
 
-    install_lang(message.lang.clone(), &message.version, lang_versions)
+    install_lang(lang, &message.version, lang_versions)
         .await
         .map_err(RunLangError::PluginInstallFailure)?;
 
-    let _semaphore = RUNS_SEMAPHORE
-        .acquire()
+    // Neither version can be uninstalled until the run is done
+    let _judge_toolchain = start_job(judge_lang, &judge_version)?;
+    let _toolchain = start_job(lang, &message.version)?;
+
+    let _permit = SCHEDULER
+        .acquire(message.priority, |position| {
+            if let Some(events) = &events {
+                let _ = events.send(RunLangEvent::Queued { position });
+            }
+        })
         .await
         .map_err(RunLangError::SemaphoreError)?;
-    let output = run_lang(
-        &message.lang,
-        &message.version,
-        &message.code,
-        &message.judge,
-        "deno",
-        deno_latest_version,
-    )
-    .await
-    .map_err(RunLangError::RunLang)?;
+    if message.benchmark {
+        return run_benchmark(&message, lang, judge_lang, &judge_version, events)
+            .await
+            .map_err(RunLangError::RunLang);
+    }
+
+    let mut output = run_lang(&message, lang, judge_lang, &judge_version, events)
+        .await
+        .map_err(RunLangError::RunLang)?;
+    output.input_hash = Some(input_hash);
+    RESULT_CACHE.insert(&output);
     Ok(output)
 }
 
@@ -264,17 +510,23 @@ async fn get_versions_for_language(line: &str) -> (String, CacheMap<String, ()>)
         .unwrap();
 
     if !versions.status.success() {
-        println!("Finding versions failed");
+        eprintln!("Finding versions failed");
     }
 
-    return (
+    let versions: Vec<String> = String::from_utf8(versions.stdout)
+        .unwrap()
+        .lines()
+        .map(|k| k.trim().trim_start_matches('*').to_owned())
+        .collect();
+    register_installed(name, &versions);
+
+    (
         (*name).to_owned(),
-        String::from_utf8(versions.stdout)
-            .unwrap()
-            .lines()
-            .map(|k| (k.trim().to_owned(), ()))
+        versions
+            .into_iter()
+            .map(|version| (version, ()))
             .collect::<CacheMap<_, ()>>(),
-    );
+    )
 }
 
 pub async fn get_lang_versions() -> CacheMap<String, CacheMap<String, ()>> {
@@ -0,0 +1,58 @@
+use std::{
+    io,
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    process::Command,
+};
+
+use super::{seccomp, Mount, Sandbox, SandboxSpec, SandboxedCommand};
+
+/// Runs the process with bubblewrap, in new namespaces of every kind and under the seccomp
+/// filter
+pub struct Bwrap;
+
+impl Sandbox for Bwrap {
+    fn command(&self, spec: &SandboxSpec) -> io::Result<SandboxedCommand> {
+        let mut command = Command::new("bwrap");
+        // Only the environment of the spec, the runner's own has its secret
+        command.args(["--die-with-parent", "--clearenv"]);
+        for mount in &spec.mounts {
+            match mount {
+                Mount::ReadOnly { source, target } => {
+                    command.arg("--ro-bind").arg(source).arg(target)
+                }
+                Mount::Tmpfs { target } => command.arg("--tmpfs").arg(target),
+                Mount::Proc { target } => command.arg("--proc").arg(target),
+            };
+        }
+        for (key, value) in &spec.env {
+            command.args(["--setenv", key, value]);
+        }
+
+        // bwrap reads the filter from a file descriptor, which is only inherited by the child
+        let seccomp_file = seccomp::bpf_program_file()?;
+        let seccomp_fd = seccomp_file.as_raw_fd();
+        // SAFETY: fcntl is async-signal-safe
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(seccomp_fd, libc::F_SETFD, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        command
+            .arg("--chdir")
+            .arg(&spec.cwd)
+            .args(["--unshare-all", "--new-session"])
+            .arg("--seccomp")
+            .arg(seccomp_fd.to_string())
+            .args(&spec.command);
+
+        Ok(SandboxedCommand {
+            command,
+            temp_dirs: vec![],
+            inherited_files: vec![seccomp_file],
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use tempfile::TempDir;
+
+use super::{Mount, Sandbox, SandboxSpec, SandboxedCommand};
+
+/// Runs the process directly, without any isolation. Paths inside mounts are translated to
+/// where they are on the host, and every tmpfs becomes a temporary directory.
+pub struct Local;
+
+impl Sandbox for Local {
+    fn command(&self, spec: &SandboxSpec) -> io::Result<SandboxedCommand> {
+        let mut temp_dirs = vec![];
+        let mut mapping: Vec<(&Path, PathBuf)> = vec![];
+        for mount in &spec.mounts {
+            match mount {
+                Mount::ReadOnly { source, target } => mapping.push((target, source.clone())),
+                Mount::Tmpfs { target } => {
+                    let temp_dir = TempDir::new()?;
+                    mapping.push((target, temp_dir.path().to_owned()));
+                    temp_dirs.push(temp_dir);
+                }
+                // The process can see everything anyway
+                Mount::Proc { .. } => {}
+            }
+        }
+        // Nested mounts take precedence over the mounts they're in
+        mapping.sort_by_key(|(target, _)| std::cmp::Reverse(target.components().count()));
+
+        let translate = |value: &str| -> String {
+            for (target, source) in &mapping {
+                match Path::new(value).strip_prefix(target) {
+                    Ok(rest) if rest.as_os_str().is_empty() => {
+                        return source.to_string_lossy().into_owned()
+                    }
+                    Ok(rest) => return source.join(rest).to_string_lossy().into_owned(),
+                    Err(_) => {}
+                }
+            }
+            value.to_owned()
+        };
+
+        let (program, args) = spec
+            .command
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty command"))?;
+        let mut command = Command::new(translate(program));
+        command
+            .args(args.iter().map(|arg| translate(arg)))
+            .env_clear()
+            .envs(spec.env.iter().map(|(key, value)| (key, translate(value))))
+            .current_dir(translate(&spec.cwd.to_string_lossy()));
+
+        Ok(SandboxedCommand {
+            command,
+            temp_dirs,
+            inherited_files: vec![],
+        })
+    }
+}
@@ -0,0 +1,96 @@
+mod bwrap;
+mod local;
+mod nsjail;
+mod seccomp;
+
+use std::{fs::File, io, path::PathBuf, str::FromStr, sync::LazyLock};
+
+use tempfile::TempDir;
+
+use crate::config::env_or;
+
+/// A directory the sandboxed process can see
+pub enum Mount {
+    /// A host directory, mounted read only
+    ReadOnly { source: PathBuf, target: PathBuf },
+    /// An empty writable directory that only lasts for the run
+    Tmpfs { target: PathBuf },
+    /// A `/proc` that only shows the processes in the sandbox
+    Proc { target: PathBuf },
+}
+
+impl Mount {
+    pub fn read_only(source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        Mount::ReadOnly {
+            source: source.into(),
+            target: target.into(),
+        }
+    }
+
+    pub fn tmpfs(target: impl Into<PathBuf>) -> Self {
+        Mount::Tmpfs {
+            target: target.into(),
+        }
+    }
+
+    pub fn proc(target: impl Into<PathBuf>) -> Self {
+        Mount::Proc {
+            target: target.into(),
+        }
+    }
+}
+
+/// What to run in a sandbox and what it can see. Paths in the command, environment and working
+/// directory are as seen from inside the sandbox.
+pub struct SandboxSpec {
+    pub mounts: Vec<Mount>,
+    pub env: Vec<(String, String)>,
+    pub cwd: PathBuf,
+    pub command: Vec<String>,
+}
+
+/// A command that runs in a sandbox, ready to be spawned
+pub struct SandboxedCommand {
+    pub command: std::process::Command,
+    /// Directories the sandbox created on the host, which are removed when this is dropped.
+    /// Keep them until the process exits.
+    pub temp_dirs: Vec<TempDir>,
+    /// Files the process inherits, which must stay open until it's spawned
+    pub inherited_files: Vec<File>,
+}
+
+pub trait Sandbox: Send + Sync {
+    fn command(&self, spec: &SandboxSpec) -> io::Result<SandboxedCommand>;
+}
+
+#[derive(Clone, Copy)]
+enum SandboxKind {
+    Bwrap,
+    Nsjail,
+    Local,
+}
+
+impl FromStr for SandboxKind {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bwrap" => Ok(SandboxKind::Bwrap),
+            "nsjail" => Ok(SandboxKind::Nsjail),
+            "local" => Ok(SandboxKind::Local),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The sandbox every judge runs in, chosen with `YQ_SANDBOX`: `bwrap` (the default), `nsjail`,
+/// or `local`. The local sandbox doesn't isolate anything, it's only meant for development.
+pub static SANDBOX: LazyLock<Box<dyn Sandbox>> =
+    LazyLock::new(|| match env_or("YQ_SANDBOX", SandboxKind::Bwrap) {
+        SandboxKind::Bwrap => Box::new(bwrap::Bwrap),
+        SandboxKind::Nsjail => Box::new(nsjail::Nsjail),
+        SandboxKind::Local => {
+            eprintln!("Running judges without a sandbox, only do this for development");
+            Box::new(local::Local)
+        }
+    });
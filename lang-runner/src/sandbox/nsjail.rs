@@ -0,0 +1,68 @@
+use std::{io, process::Command};
+
+use super::{seccomp, Mount, Sandbox, SandboxSpec, SandboxedCommand};
+
+/// Runs the process with nsjail, in new namespaces of every kind and under the seccomp filter
+pub struct Nsjail;
+
+impl Sandbox for Nsjail {
+    fn command(&self, spec: &SandboxSpec) -> io::Result<SandboxedCommand> {
+        let mut command = Command::new("nsjail");
+        command.args([
+            "--mode",
+            "o",
+            "--quiet",
+            // The runner enforces its own time limit
+            "--time_limit",
+            "0",
+            // Keep the rlimits the runner set instead of the nsjail defaults. Address space
+            // isn't limited since that breaks V8.
+            "--rlimit_as",
+            "inf",
+            "--rlimit_cpu",
+            "hard",
+            "--rlimit_fsize",
+            "hard",
+            "--rlimit_nofile",
+            "hard",
+            "--rlimit_nproc",
+            "hard",
+        ]);
+        command.arg("--seccomp_string").arg(seccomp::kafel_policy());
+
+        let mut proc = None;
+        for mount in &spec.mounts {
+            match mount {
+                Mount::ReadOnly { source, target } => {
+                    let mut bind = source.clone().into_os_string();
+                    bind.push(":");
+                    bind.push(target);
+                    command.arg("--bindmount_ro").arg(bind);
+                }
+                Mount::Tmpfs { target } => {
+                    command.arg("--tmpfsmount").arg(target);
+                }
+                Mount::Proc { target } => proc = Some(target),
+            }
+        }
+        match proc {
+            Some(target) => command.arg("--proc_path").arg(target),
+            None => command.arg("--disable_proc"),
+        };
+
+        for (key, value) in &spec.env {
+            command.arg("--env").arg(format!("{key}={value}"));
+        }
+        command
+            .arg("--cwd")
+            .arg(&spec.cwd)
+            .arg("--")
+            .args(&spec.command);
+
+        Ok(SandboxedCommand {
+            command,
+            temp_dirs: vec![],
+            inherited_files: vec![],
+        })
+    }
+}
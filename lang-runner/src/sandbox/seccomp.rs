@@ -0,0 +1,383 @@
+use std::io::{self, Seek, Write};
+
+/// Syscalls that let a process escape or attack the sandbox. In the seccomp filter every
+/// sandboxed process runs under they fail with `EPERM`, everything else is allowed. By name for
+/// nsjail and by number for bwrap.
+const BLOCKED_SYSCALLS: &[(&str, libc::c_long)] = &[
+    // Inspecting and changing other processes
+    ("ptrace", libc::SYS_ptrace),
+    ("process_vm_readv", libc::SYS_process_vm_readv),
+    ("process_vm_writev", libc::SYS_process_vm_writev),
+    // Changing the file system the sandbox sees
+    ("mount", libc::SYS_mount),
+    ("umount2", libc::SYS_umount2),
+    ("pivot_root", libc::SYS_pivot_root),
+    ("chroot", libc::SYS_chroot),
+    ("open_tree", libc::SYS_open_tree),
+    ("move_mount", libc::SYS_move_mount),
+    ("fsopen", libc::SYS_fsopen),
+    ("fsconfig", libc::SYS_fsconfig),
+    ("fsmount", libc::SYS_fsmount),
+    ("fspick", libc::SYS_fspick),
+    ("mount_setattr", libc::SYS_mount_setattr),
+    ("open_by_handle_at", libc::SYS_open_by_handle_at),
+    ("name_to_handle_at", libc::SYS_name_to_handle_at),
+    // New namespaces would give the process capabilities again
+    ("unshare", libc::SYS_unshare),
+    ("setns", libc::SYS_setns),
+    // The kernel keyring isn't namespaced
+    ("keyctl", libc::SYS_keyctl),
+    ("add_key", libc::SYS_add_key),
+    ("request_key", libc::SYS_request_key),
+    // Large attack surfaces in the kernel
+    ("bpf", libc::SYS_bpf),
+    ("perf_event_open", libc::SYS_perf_event_open),
+    ("userfaultfd", libc::SYS_userfaultfd),
+    ("io_uring_setup", libc::SYS_io_uring_setup),
+    ("io_uring_enter", libc::SYS_io_uring_enter),
+    ("io_uring_register", libc::SYS_io_uring_register),
+    // The whole machine
+    ("kexec_load", libc::SYS_kexec_load),
+    ("kexec_file_load", libc::SYS_kexec_file_load),
+    ("init_module", libc::SYS_init_module),
+    ("finit_module", libc::SYS_finit_module),
+    ("delete_module", libc::SYS_delete_module),
+    ("reboot", libc::SYS_reboot),
+    ("swapon", libc::SYS_swapon),
+    ("swapoff", libc::SYS_swapoff),
+    ("acct", libc::SYS_acct),
+    ("quotactl", libc::SYS_quotactl),
+    ("syslog", libc::SYS_syslog),
+    ("vhangup", libc::SYS_vhangup),
+    ("settimeofday", libc::SYS_settimeofday),
+    ("clock_settime", libc::SYS_clock_settime),
+    ("clock_adjtime", libc::SYS_clock_adjtime),
+    ("adjtimex", libc::SYS_adjtimex),
+    #[cfg(target_arch = "x86_64")]
+    ("iopl", libc::SYS_iopl),
+    #[cfg(target_arch = "x86_64")]
+    ("ioperm", libc::SYS_ioperm),
+];
+
+/// `clone` with any of these flags creates namespaces, like `unshare`
+const NAMESPACE_FLAGS: libc::c_int = libc::CLONE_NEWNS
+    | libc::CLONE_NEWCGROUP
+    | libc::CLONE_NEWUTS
+    | libc::CLONE_NEWIPC
+    | libc::CLONE_NEWUSER
+    | libc::CLONE_NEWPID
+    | libc::CLONE_NEWNET
+    | 0x80; // CLONE_NEWTIME
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Syscall numbers with this bit set are the x32 ABI, which would bypass the filter
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets in `struct seccomp_data`
+const SYSCALL_NUMBER_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+/// The low half of the first argument
+#[cfg(target_endian = "little")]
+const FIRST_ARGUMENT_OFFSET: u32 = 16;
+#[cfg(target_endian = "big")]
+const FIRST_ARGUMENT_OFFSET: u32 = 20;
+
+/// Where a conditional jump goes
+#[derive(Clone, Copy)]
+enum Jump {
+    Next,
+    Allow,
+    Deny,
+    NotImplemented,
+    Kill,
+}
+
+/// The returns at the end of the program, in the order of `Jump`
+const RETURNS: [u32; 4] = [
+    libc::SECCOMP_RET_ALLOW,
+    libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32,
+    libc::SECCOMP_RET_KILL_PROCESS,
+];
+
+struct Instruction {
+    code: u32,
+    jump_true: Jump,
+    jump_false: Jump,
+    k: u32,
+}
+
+impl Instruction {
+    fn load(offset: u32) -> Self {
+        Instruction {
+            code: libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+            jump_true: Jump::Next,
+            jump_false: Jump::Next,
+            k: offset,
+        }
+    }
+
+    fn jump(condition: u32, k: u32, jump_true: Jump, jump_false: Jump) -> Self {
+        Instruction {
+            code: libc::BPF_JMP | condition | libc::BPF_K,
+            jump_true,
+            jump_false,
+            k,
+        }
+    }
+}
+
+/// The filter as a classic BPF program, in the format bwrap reads from `--seccomp`
+fn bpf_program() -> Vec<u8> {
+    let mut program = vec![
+        // Syscalls of other architectures have other numbers
+        Instruction::load(ARCH_OFFSET),
+        Instruction::jump(libc::BPF_JEQ, AUDIT_ARCH, Jump::Next, Jump::Kill),
+        Instruction::load(SYSCALL_NUMBER_OFFSET),
+    ];
+    #[cfg(target_arch = "x86_64")]
+    program.push(Instruction::jump(
+        libc::BPF_JGE,
+        X32_SYSCALL_BIT,
+        Jump::Deny,
+        Jump::Next,
+    ));
+    program.extend(BLOCKED_SYSCALLS.iter().map(|(_, number)| {
+        Instruction::jump(libc::BPF_JEQ, *number as u32, Jump::Deny, Jump::Next)
+    }));
+    // The flags of clone3 are behind a pointer, so it can't be checked. libc falls back to
+    // clone when it isn't implemented.
+    program.push(Instruction::jump(
+        libc::BPF_JEQ,
+        libc::SYS_clone3 as u32,
+        Jump::NotImplemented,
+        Jump::Next,
+    ));
+    program.extend([
+        Instruction::jump(
+            libc::BPF_JEQ,
+            libc::SYS_clone as u32,
+            Jump::Next,
+            Jump::Allow,
+        ),
+        Instruction::load(FIRST_ARGUMENT_OFFSET),
+        Instruction::jump(
+            libc::BPF_JSET,
+            NAMESPACE_FLAGS as u32,
+            Jump::Deny,
+            Jump::Allow,
+        ),
+    ]);
+
+    let length = program.len();
+    let offset = |index: usize, jump: Jump| -> u8 {
+        let target = match jump {
+            Jump::Next => return 0,
+            Jump::Allow => length,
+            Jump::Deny => length + 1,
+            Jump::NotImplemented => length + 2,
+            Jump::Kill => length + 3,
+        };
+        u8::try_from(target - index - 1).expect("The filter is short enough to jump anywhere")
+    };
+
+    let mut bytes = Vec::with_capacity((length + RETURNS.len()) * 8);
+    let mut push = |code: u32, jump_true: u8, jump_false: u8, k: u32| {
+        // struct sock_filter
+        bytes.extend((code as u16).to_ne_bytes());
+        bytes.extend([jump_true, jump_false]);
+        bytes.extend(k.to_ne_bytes());
+    };
+    for (index, instruction) in program.iter().enumerate() {
+        push(
+            instruction.code,
+            offset(index, instruction.jump_true),
+            offset(index, instruction.jump_false),
+            instruction.k,
+        );
+    }
+    for value in RETURNS {
+        push(libc::BPF_RET | libc::BPF_K, 0, 0, value);
+    }
+    bytes
+}
+
+/// The same filter as a Kafel policy, for nsjail's `--seccomp_string`
+pub fn kafel_policy() -> String {
+    let blocked = BLOCKED_SYSCALLS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "ERRNO({}) {{ {blocked}, clone {{ (clone_flags & {:#x}) != 0 }} }} \
+         ERRNO({}) {{ clone3 }} \
+         DEFAULT ALLOW",
+        libc::EPERM,
+        NAMESPACE_FLAGS,
+        libc::ENOSYS,
+    )
+}
+
+/// A file with the BPF program, at the start so bwrap can read all of it
+pub fn bpf_program_file() -> io::Result<std::fs::File> {
+    let mut file = tempfile::tempfile()?;
+    file.write_all(&bpf_program())?;
+    file.rewind()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the BPF program on a `struct seccomp_data` like the kernel would, with only the
+    /// instructions `bpf_program` emits
+    fn run_filter(arch: u32, number: libc::c_long, first_argument: u64) -> u32 {
+        let mut data = [0u8; 64];
+        data[0..4].copy_from_slice(&(number as u32).to_ne_bytes());
+        data[4..8].copy_from_slice(&arch.to_ne_bytes());
+        data[16..24].copy_from_slice(&first_argument.to_ne_bytes());
+
+        let program = bpf_program();
+        let instructions: Vec<_> = program
+            .chunks(8)
+            .map(|chunk| {
+                (
+                    u16::from_ne_bytes([chunk[0], chunk[1]]) as u32,
+                    chunk[2] as usize,
+                    chunk[3] as usize,
+                    u32::from_ne_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]),
+                )
+            })
+            .collect();
+
+        let mut accumulator = 0;
+        let mut pc = 0;
+        loop {
+            let (code, jump_true, jump_false, k) = instructions[pc];
+            pc += 1;
+            if code == libc::BPF_LD | libc::BPF_W | libc::BPF_ABS {
+                let offset = k as usize;
+                accumulator = u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+                continue;
+            }
+            if code == libc::BPF_RET | libc::BPF_K {
+                return k;
+            }
+            let taken = match code {
+                c if c == libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K => accumulator == k,
+                c if c == libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K => accumulator >= k,
+                c if c == libc::BPF_JMP | libc::BPF_JSET | libc::BPF_K => accumulator & k != 0,
+                _ => panic!("Unexpected instruction {code:#x}"),
+            };
+            pc += if taken { jump_true } else { jump_false };
+        }
+    }
+
+    fn run_syscall(number: libc::c_long, first_argument: u64) -> u32 {
+        run_filter(AUDIT_ARCH, number, first_argument)
+    }
+
+    const DENY: u32 = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    #[test]
+    fn blocks_escape_syscalls() {
+        for (name, number) in BLOCKED_SYSCALLS {
+            assert_eq!(run_syscall(*number, 0), DENY, "{name} isn't blocked");
+        }
+    }
+
+    #[test]
+    fn allows_other_syscalls() {
+        for number in [
+            libc::SYS_read,
+            libc::SYS_write,
+            libc::SYS_openat,
+            libc::SYS_mmap,
+            libc::SYS_execve,
+            libc::SYS_exit_group,
+        ] {
+            assert_eq!(run_syscall(number, 0), libc::SECCOMP_RET_ALLOW);
+        }
+    }
+
+    #[test]
+    fn clone3_is_not_implemented() {
+        assert_eq!(
+            run_syscall(libc::SYS_clone3, 0),
+            libc::SECCOMP_RET_ERRNO | libc::ENOSYS as u32
+        );
+    }
+
+    #[test]
+    fn clone_without_namespaces_is_allowed() {
+        let thread_flags = libc::CLONE_VM
+            | libc::CLONE_FS
+            | libc::CLONE_FILES
+            | libc::CLONE_SIGHAND
+            | libc::CLONE_THREAD
+            | libc::CLONE_SYSVSEM;
+        for flags in [libc::SIGCHLD, thread_flags] {
+            assert_eq!(
+                run_syscall(libc::SYS_clone, flags as u64),
+                libc::SECCOMP_RET_ALLOW
+            );
+        }
+    }
+
+    #[test]
+    fn clone_with_namespaces_is_denied() {
+        for flag in [
+            libc::CLONE_NEWNS,
+            libc::CLONE_NEWCGROUP,
+            libc::CLONE_NEWUTS,
+            libc::CLONE_NEWIPC,
+            libc::CLONE_NEWUSER,
+            libc::CLONE_NEWPID,
+            libc::CLONE_NEWNET,
+            0x80,
+        ] {
+            assert_eq!(
+                run_syscall(libc::SYS_clone, (flag | libc::SIGCHLD) as u64),
+                DENY,
+                "clone with {flag:#x} isn't denied"
+            );
+        }
+    }
+
+    #[test]
+    fn other_architectures_are_killed() {
+        // i386
+        assert_eq!(
+            run_filter(0x4000_0003, libc::SYS_read, 0),
+            libc::SECCOMP_RET_KILL_PROCESS
+        );
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn x32_syscalls_are_denied() {
+        assert_eq!(
+            run_syscall(X32_SYSCALL_BIT as libc::c_long | libc::SYS_read, 0),
+            DENY
+        );
+    }
+
+    #[test]
+    fn kafel_policy_blocks_the_same_syscalls() {
+        let policy = kafel_policy();
+        for (name, _) in BLOCKED_SYSCALLS {
+            assert!(
+                policy.contains(&format!(" {name},")),
+                "{name} isn't in {policy}"
+            );
+        }
+        assert!(policy.contains(&format!("ERRNO({}) {{ clone3 }}", libc::ENOSYS)));
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+};
+
+use common::RunPriority;
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::config::env_or;
+
+/// Limits how many runs execute at the same time. Queued runs are started in order of
+/// priority, and first come first served within the same priority.
+pub struct Scheduler {
+    max_concurrent_runs: usize,
+    state: Mutex<SchedulerState>,
+}
+
+struct SchedulerState {
+    running: usize,
+    /// One queue per priority, highest priority first
+    queues: [VecDeque<oneshot::Sender<()>>; 2],
+}
+
+pub static SCHEDULER: LazyLock<Scheduler> = LazyLock::new(Scheduler::from_env);
+
+fn queue_index(priority: RunPriority) -> usize {
+    match priority {
+        RunPriority::Interactive => 0,
+        RunPriority::Revalidation => 1,
+    }
+}
+
+#[derive(Serialize)]
+pub struct QueueStatus {
+    max_concurrent_runs: usize,
+    running: usize,
+    interactive: usize,
+    revalidation: usize,
+}
+
+/// A slot to execute a run, the next queued run starts when this is dropped
+pub struct RunPermit {
+    scheduler: &'static Scheduler,
+}
+
+impl Drop for RunPermit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+/// Makes sure a slot that was handed to a run doesn't leak if the run is cancelled before
+/// it notices
+struct QueuedRun {
+    receiver: oneshot::Receiver<()>,
+    scheduler: &'static Scheduler,
+}
+
+impl Drop for QueuedRun {
+    fn drop(&mut self) {
+        if self.receiver.try_recv().is_ok() {
+            self.scheduler.release();
+        }
+    }
+}
+
+impl Scheduler {
+    fn from_env() -> Self {
+        let max_concurrent_runs = env_or(
+            "YQ_MAX_CONCURRENT_RUNS",
+            std::thread::available_parallelism().map_or(4, |k| k.get()),
+        );
+        // No run could ever start
+        assert!(
+            max_concurrent_runs >= 1,
+            "YQ_MAX_CONCURRENT_RUNS must be at least 1"
+        );
+
+        Scheduler {
+            max_concurrent_runs,
+            state: Mutex::new(SchedulerState {
+                running: 0,
+                queues: Default::default(),
+            }),
+        }
+    }
+
+    /// Waits until a run with this priority may start. If it has to wait `on_queued` is called
+    /// with the number of runs ahead of it.
+    pub async fn acquire(
+        &'static self,
+        priority: RunPriority,
+        on_queued: impl FnOnce(usize),
+    ) -> Result<RunPermit, oneshot::error::RecvError> {
+        let (mut queued_run, position) = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.max_concurrent_runs
+                && state.queues.iter().all(VecDeque::is_empty)
+            {
+                state.running += 1;
+                return Ok(RunPermit { scheduler: self });
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let index = queue_index(priority);
+            let position = state.queues[..=index].iter().map(VecDeque::len).sum();
+            state.queues[index].push_back(sender);
+            (
+                QueuedRun {
+                    receiver,
+                    scheduler: self,
+                },
+                position,
+            )
+        };
+
+        on_queued(position);
+        (&mut queued_run.receiver).await?;
+        Ok(RunPermit { scheduler: self })
+    }
+
+    /// Takes a free slot without waiting, for programs a judge runs concurrently. Never takes
+    /// a slot that a queued run is waiting for.
+    pub fn try_acquire(&'static self) -> Option<RunPermit> {
+        let mut state = self.state.lock().unwrap();
+        if state.running < self.max_concurrent_runs && state.queues.iter().all(VecDeque::is_empty) {
+            state.running += 1;
+            Some(RunPermit { scheduler: self })
+        } else {
+            None
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        // Hand the slot straight to the next run, skipping runs that were cancelled
+        while let Some(next) = state.queues.iter_mut().find_map(VecDeque::pop_front) {
+            if next.send(()).is_ok() {
+                return;
+            }
+        }
+        state.running -= 1;
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        let state = self.state.lock().unwrap();
+        QueueStatus {
+            max_concurrent_runs: self.max_concurrent_runs,
+            running: state.running,
+            interactive: state.queues[queue_index(RunPriority::Interactive)].len(),
+            revalidation: state.queues[queue_index(RunPriority::Revalidation)].len(),
+        }
+    }
+}
@@ -0,0 +1,557 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_process::Command;
+use axum::{
+    extract::{Path as UrlPath, Query, State},
+    http::StatusCode,
+    Json,
+};
+use common::langs::{is_valid_version, langs, Lang, Langs};
+use futures_util::{io::BufReader, AsyncBufReadExt, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    artifacts::{asdf_data_dir, install_artifact, plugin_source, OFFLINE},
+    cachemap::CacheMap,
+    config::env_or,
+    error::{RunLangError, RunProcessError},
+    run::{get_lang_directory, install_lang},
+};
+
+type LangVersions = CacheMap<String, CacheMap<String, ()>>;
+
+/// How much of the output of an install is kept, the start is dropped first
+const MAX_LOG_BYTES: usize = 64 * 1024;
+
+/// How long a version must go unused before garbage collection removes it, from
+/// `YQ_TOOLCHAIN_GC_IDLE_SECS`. One week by default.
+static GC_IDLE_SECS: LazyLock<u64> =
+    LazyLock::new(|| env_or("YQ_TOOLCHAIN_GC_IDLE_SECS", 7 * 24 * 60 * 60));
+
+/// Where the lockfile of the installed toolchains is written, from `YQ_TOOLCHAINS_LOCKFILE`.
+/// `toolchains.lock.json` in the asdf data directory by default.
+static LOCKFILE: LazyLock<PathBuf> = LazyLock::new(|| {
+    std::env::var_os("YQ_TOOLCHAINS_LOCKFILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| asdf_data_dir().join("toolchains.lock.json"))
+});
+
+/// Installed toolchains by plugin name and version, the format of the lockfile
+type Lock = BTreeMap<String, BTreeMap<String, ToolchainSource>>;
+
+/// Where an installed version came from
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum ToolchainSource {
+    /// It was installed before the runner started, and isn't in the lockfile
+    Preinstalled,
+    Asdf {
+        plugin: String,
+    },
+    Artifact {
+        file: String,
+        sha256: String,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallState {
+    Installing,
+    Installed,
+    Failed,
+    Uninstalling,
+}
+
+/// An installed version of an asdf plugin, or one that is being installed
+struct Toolchain {
+    state: InstallState,
+    /// The output of the last install or uninstall
+    log: String,
+    /// When the last install or uninstall started
+    started: Instant,
+    /// Jobs running with this version, it can't be uninstalled while there are any
+    jobs: usize,
+    last_used: Instant,
+    source: ToolchainSource,
+}
+
+impl Toolchain {
+    fn new(state: InstallState, source: ToolchainSource) -> Self {
+        Toolchain {
+            state,
+            source,
+            log: String::new(),
+            started: Instant::now(),
+            jobs: 0,
+            last_used: Instant::now(),
+        }
+    }
+
+    fn append_log(&mut self, line: &str) {
+        self.log.push_str(line);
+        self.log.push('\n');
+        if self.log.len() > MAX_LOG_BYTES {
+            let mut start = self.log.len() - MAX_LOG_BYTES;
+            while !self.log.is_char_boundary(start) {
+                start += 1;
+            }
+            self.log.drain(..start);
+        }
+    }
+}
+
+/// Toolchains by plugin name and version
+static TOOLCHAINS: LazyLock<Mutex<HashMap<(String, String), Toolchain>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn key(plugin_name: &str, version: &str) -> (String, String) {
+    (plugin_name.to_owned(), version.to_owned())
+}
+
+/// The lockfile the runner wrote before it was last stopped
+static PREVIOUS_LOCK: LazyLock<Lock> = LazyLock::new(|| {
+    let Ok(text) = std::fs::read_to_string(&*LOCKFILE) else {
+        return Lock::new();
+    };
+    serde_json::from_str(&text).unwrap_or_else(|e| {
+        eprintln!("Ignoring the invalid lockfile {:?}: {e}", *LOCKFILE);
+        Lock::new()
+    })
+});
+
+/// Records the versions of a plugin asdf already had when the runner started, with their
+/// sources from the previous lockfile
+pub fn register_installed(plugin_name: &str, versions: &[String]) {
+    let mut toolchains = TOOLCHAINS.lock().unwrap();
+    for version in versions {
+        let source = PREVIOUS_LOCK
+            .get(plugin_name)
+            .and_then(|versions| versions.get(version))
+            .cloned()
+            .unwrap_or(ToolchainSource::Preinstalled);
+        toolchains.insert(
+            key(plugin_name, version),
+            Toolchain::new(InstallState::Installed, source),
+        );
+    }
+}
+
+/// Writes the installed toolchains to the lockfile, so builds can check that they installed
+/// exactly the same ones. Failing to write it doesn't fail the install.
+fn write_lock(toolchains: &HashMap<(String, String), Toolchain>) {
+    let mut lock = Lock::new();
+    for ((plugin_name, version), toolchain) in toolchains {
+        if toolchain.state == InstallState::Installed {
+            lock.entry(plugin_name.clone())
+                .or_default()
+                .insert(version.clone(), toolchain.source.clone());
+        }
+    }
+
+    let write = || -> std::io::Result<()> {
+        let mut text = serde_json::to_string_pretty(&lock)?;
+        text.push('\n');
+        // Written next to it and renamed, so it's never read half written
+        let temporary = LOCKFILE.with_extension("tmp");
+        std::fs::write(&temporary, text)?;
+        std::fs::rename(temporary, &*LOCKFILE)
+    };
+    if let Err(e) = write() {
+        eprintln!("Failed to write the lockfile {:?}: {e}", *LOCKFILE);
+    }
+}
+
+/// Writes the lockfile with the versions found at startup
+pub fn write_lockfile() {
+    write_lock(&TOOLCHAINS.lock().unwrap());
+}
+
+/// Prints a line of an install and adds it to the log of the toolchain
+fn log_line(plugin_name: &str, version: &str, line: &str) {
+    eprintln!("{line}");
+    if let Some(toolchain) = TOOLCHAINS
+        .lock()
+        .unwrap()
+        .get_mut(&key(plugin_name, version))
+    {
+        toolchain.append_log(line);
+    }
+}
+
+/// Runs an asdf command that changes a toolchain, keeping its output as the log of the toolchain
+/// while printing it like before
+async fn run_logged(
+    plugin_name: &str,
+    version: &str,
+    mut command: Command,
+) -> Result<(), RunProcessError> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = BufReader::new(child.stdout.take().expect("The child stdout exists")).lines();
+    let stderr = BufReader::new(child.stderr.take().expect("The child stderr exists")).lines();
+    let mut lines = futures_util::stream::select(stdout, stderr);
+    while let Some(line) = lines.next().await {
+        // Output that isn't UTF-8 is left out of the log
+        let Ok(line) = line else { continue };
+        log_line(plugin_name, version, &line);
+    }
+
+    let status = child.status().await?;
+    if !status.success() {
+        return Err(RunProcessError::NonZeroStatusCode(status.code()));
+    }
+    Ok(())
+}
+
+/// Installs a version of a language from its artifact if it has one, with asdf otherwise,
+/// recording its progress
+pub async fn install_toolchain(lang: &Lang, version: &str) -> Result<(), RunProcessError> {
+    let plugin_name = &lang.plugin_name;
+    {
+        let mut toolchains = TOOLCHAINS.lock().unwrap();
+        let toolchain = toolchains
+            .entry(key(plugin_name, version))
+            .or_insert_with(|| {
+                Toolchain::new(InstallState::Installing, ToolchainSource::Preinstalled)
+            });
+        toolchain.state = InstallState::Installing;
+        toolchain.log.clear();
+        toolchain.started = Instant::now();
+    }
+
+    let result = match lang.artifacts.get(version) {
+        Some(artifact) => install_artifact(plugin_name, version, artifact, |line| {
+            log_line(plugin_name, version, line)
+        })
+        .await
+        .map(|()| ToolchainSource::Artifact {
+            file: artifact.file.clone(),
+            sha256: artifact.sha256.to_ascii_lowercase(),
+        }),
+        None if *OFFLINE => Err(RunProcessError::NotMirrored(format!(
+            "{plugin_name} {version} has no artifact"
+        ))),
+        None => {
+            let mut command = Command::new("asdf");
+            command.args(["install", plugin_name, version]);
+            match run_logged(plugin_name, version, command).await {
+                Ok(()) => plugin_source(lang).map(|plugin| ToolchainSource::Asdf { plugin }),
+                Err(e) => Err(e),
+            }
+        }
+    };
+
+    let mut toolchains = TOOLCHAINS.lock().unwrap();
+    if let Some(toolchain) = toolchains.get_mut(&key(plugin_name, version)) {
+        match &result {
+            Ok(source) => {
+                toolchain.state = InstallState::Installed;
+                toolchain.source = source.clone();
+            }
+            Err(e) => {
+                toolchain.append_log(&format!("Install failed: {e:?}"));
+                toolchain.state = InstallState::Failed;
+            }
+        }
+    }
+    write_lock(&toolchains);
+    result.map(|_| ())
+}
+
+/// Counts as a job using a toolchain until it's dropped, so it isn't uninstalled meanwhile
+pub struct ToolchainJob((String, String));
+
+impl Drop for ToolchainJob {
+    fn drop(&mut self) {
+        if let Some(toolchain) = TOOLCHAINS.lock().unwrap().get_mut(&self.0) {
+            toolchain.jobs -= 1;
+            toolchain.last_used = Instant::now();
+        }
+    }
+}
+
+/// Marks an installed toolchain as used by a job. Fails if it's being uninstalled.
+pub fn start_job(lang: &Lang, version: &str) -> Result<ToolchainJob, RunLangError> {
+    let key = key(&lang.plugin_name, version);
+    let mut toolchains = TOOLCHAINS.lock().unwrap();
+    match toolchains.get_mut(&key) {
+        Some(toolchain) if toolchain.state == InstallState::Installed => {
+            toolchain.jobs += 1;
+            toolchain.last_used = Instant::now();
+            Ok(ToolchainJob(key))
+        }
+        _ => Err(RunLangError::ToolchainBusy {
+            plugin: key.0,
+            version: key.1,
+        }),
+    }
+}
+
+/// Removes a version with asdf. Only versions that no job is using can be uninstalled.
+async fn uninstall_toolchain(
+    plugin_name: &str,
+    version: &str,
+    lang_versions: &LangVersions,
+) -> Result<(), RunLangError> {
+    {
+        let mut toolchains = TOOLCHAINS.lock().unwrap();
+        let toolchain = toolchains
+            .get_mut(&key(plugin_name, version))
+            .filter(|toolchain| toolchain.state != InstallState::Failed)
+            .ok_or_else(|| RunLangError::NotInstalled {
+                plugin: plugin_name.to_owned(),
+                version: version.to_owned(),
+            })?;
+        if toolchain.state != InstallState::Installed || toolchain.jobs > 0 {
+            return Err(RunLangError::ToolchainBusy {
+                plugin: plugin_name.to_owned(),
+                version: version.to_owned(),
+            });
+        }
+        toolchain.state = InstallState::Uninstalling;
+        toolchain.log.clear();
+        toolchain.started = Instant::now();
+    }
+
+    eprintln!("Uninstalling language version {plugin_name} {version}");
+    let mut command = Command::new("asdf");
+    command.args(["uninstall", plugin_name, version]);
+    let result = run_logged(plugin_name, version, command).await;
+
+    let mut toolchains = TOOLCHAINS.lock().unwrap();
+    // Jobs that come in from now on install the version again. Until now they were turned
+    // away by `start_job`.
+    if let Some(installed) = lang_versions.get_initialized(plugin_name) {
+        if let Some(installed) = installed.get() {
+            installed.remove(version);
+        }
+    }
+    match result {
+        Ok(()) => {
+            toolchains.remove(&key(plugin_name, version));
+            write_lock(&toolchains);
+            Ok(())
+        }
+        Err(e) => {
+            if let Some(toolchain) = toolchains.get_mut(&key(plugin_name, version)) {
+                toolchain.append_log(&format!("Uninstall failed: {e:?}"));
+                toolchain.state = InstallState::Failed;
+            }
+            Err(RunLangError::UninstallFailure(e))
+        }
+    }
+}
+
+/// Installs the latest version of every language in the background, so the first jobs don't
+/// wait for them. Enabled with `YQ_PREWARM=true`.
+pub fn prewarm(lang_versions: Arc<LangVersions>) {
+    if !env_or("YQ_PREWARM", false) {
+        return;
+    }
+    tokio::spawn(async move {
+        let langs = langs();
+        let installs = langs.iter().map(|(name, lang)| {
+            let lang_versions = &lang_versions;
+            async move {
+                if let Err(e) = install_lang(lang, &lang.latest_version, lang_versions).await {
+                    eprintln!("Pre-warming {name} {} failed: {e:?}", lang.latest_version);
+                }
+            }
+        });
+        futures_util::future::join_all(installs).await;
+        eprintln!("Finished pre-warming the latest version of every language");
+    });
+}
+
+/// The language of a toolchain endpoint, checking the version too
+fn resolve<'a>(lang_name: &str, version: &str, langs: &'a Langs) -> Result<&'a Lang, RunLangError> {
+    let lang = langs
+        .get(lang_name)
+        .ok_or_else(|| RunLangError::UnknownLanguage(lang_name.to_owned()))?;
+    if !is_valid_version(version) {
+        return Err(RunLangError::InvalidVersion(version.to_owned()));
+    }
+    Ok(lang)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolchainStatus {
+    plugin: String,
+    version: String,
+    state: InstallState,
+    /// Seconds since the last install or uninstall started
+    elapsed_secs: u64,
+    jobs: usize,
+    idle_secs: u64,
+    /// Only included for a single toolchain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log: Option<String>,
+}
+
+fn status(plugin_name: &str, version: &str, toolchain: &Toolchain, log: bool) -> ToolchainStatus {
+    ToolchainStatus {
+        plugin: plugin_name.to_owned(),
+        version: version.to_owned(),
+        state: toolchain.state,
+        elapsed_secs: toolchain.started.elapsed().as_secs(),
+        jobs: toolchain.jobs,
+        idle_secs: toolchain.last_used.elapsed().as_secs(),
+        log: log.then(|| toolchain.log.clone()),
+    }
+}
+
+/// Every toolchain the runner knows about
+pub async fn list_toolchains() -> Json<Vec<ToolchainStatus>> {
+    let toolchains = TOOLCHAINS.lock().unwrap();
+    let mut statuses: Vec<ToolchainStatus> = toolchains
+        .iter()
+        .map(|((plugin_name, version), toolchain)| status(plugin_name, version, toolchain, false))
+        .collect();
+    statuses.sort_by(|a, b| (&a.plugin, &a.version).cmp(&(&b.plugin, &b.version)));
+    Json(statuses)
+}
+
+/// The state of one toolchain with the log of its last install, to follow an install
+pub async fn toolchain_status(
+    UrlPath((lang_name, version)): UrlPath<(String, String)>,
+) -> Result<Json<ToolchainStatus>, RunLangError> {
+    let langs = langs();
+    let lang = resolve(&lang_name, &version, &langs)?;
+    let toolchains = TOOLCHAINS.lock().unwrap();
+    let toolchain = toolchains
+        .get(&key(&lang.plugin_name, &version))
+        .ok_or_else(|| RunLangError::NotInstalled {
+            plugin: lang.plugin_name.clone(),
+            version: version.clone(),
+        })?;
+    Ok(Json(status(&lang.plugin_name, &version, toolchain, true)))
+}
+
+/// Starts installing a version in the background, even if it isn't allowed. Its progress can be
+/// followed with `toolchain_status`.
+pub async fn start_install(
+    State(lang_versions): State<Arc<LangVersions>>,
+    UrlPath((lang_name, version)): UrlPath<(String, String)>,
+) -> Result<StatusCode, RunLangError> {
+    resolve(&lang_name, &version, &langs())?;
+    tokio::spawn(async move {
+        let langs = langs();
+        let Some(lang) = langs.get(&lang_name) else {
+            return;
+        };
+        if let Err(e) = install_lang(lang, &version, &lang_versions).await {
+            eprintln!("Installing {lang_name} {version} failed: {e:?}");
+        }
+    });
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn uninstall(
+    State(lang_versions): State<Arc<LangVersions>>,
+    UrlPath((lang_name, version)): UrlPath<(String, String)>,
+) -> Result<(), RunLangError> {
+    let langs = langs();
+    let lang = resolve(&lang_name, &version, &langs)?;
+    uninstall_toolchain(&lang.plugin_name, &version, &lang_versions).await
+}
+
+#[derive(Deserialize)]
+pub struct GcQuery {
+    /// Overrides `YQ_TOOLCHAIN_GC_IDLE_SECS`
+    idle_secs: Option<u64>,
+}
+
+/// Uninstalls the versions that no language allows and no job has used for a while, and responds
+/// with the ones it removed
+pub async fn collect_garbage(
+    State(lang_versions): State<Arc<LangVersions>>,
+    Query(query): Query<GcQuery>,
+) -> Result<Json<Vec<(String, String)>>, RunLangError> {
+    let idle = Duration::from_secs(query.idle_secs.unwrap_or(*GC_IDLE_SECS));
+    let langs = langs();
+    let unused: Vec<(String, String)> = TOOLCHAINS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|((plugin_name, version), toolchain)| {
+            toolchain.state == InstallState::Installed
+                && toolchain.jobs == 0
+                && toolchain.last_used.elapsed() >= idle
+                && !langs.iter().any(|(_, lang)| {
+                    lang.plugin_name == *plugin_name && lang.allows_version(version)
+                })
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    let mut removed = vec![];
+    for (plugin_name, version) in unused {
+        match uninstall_toolchain(&plugin_name, &version, &lang_versions).await {
+            Ok(()) => removed.push((plugin_name, version)),
+            // A job started using it in the meantime
+            Err(RunLangError::ToolchainBusy { .. }) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Json(removed))
+}
+
+#[derive(Serialize)]
+pub struct ToolchainHealth {
+    plugin: String,
+    version: String,
+    healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Checks that asdf still finds every installed toolchain and that its directory isn't empty.
+/// Responds with `503` if any of them is broken.
+pub async fn check_toolchains() -> (StatusCode, Json<Vec<ToolchainHealth>>) {
+    let installed: Vec<(String, String)> = TOOLCHAINS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, toolchain)| toolchain.state == InstallState::Installed)
+        .map(|(key, _)| key.clone())
+        .collect();
+
+    let checks = installed
+        .into_iter()
+        .map(|(plugin_name, version)| async move {
+            let error = match get_lang_directory(&plugin_name, &version).await {
+                Ok(directory) if is_non_empty_dir(&directory) => None,
+                Ok(directory) => Some(format!("{directory:?} is missing or empty")),
+                Err(e) => Some(format!("asdf can't find it: {e:?}")),
+            };
+            ToolchainHealth {
+                plugin: plugin_name,
+                version,
+                healthy: error.is_none(),
+                error,
+            }
+        });
+    let mut health = futures_util::future::join_all(checks).await;
+    health.sort_by(|a, b| (&a.plugin, &a.version).cmp(&(&b.plugin, &b.version)));
+
+    let status = if health.iter().all(|toolchain| toolchain.healthy) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
+
+fn is_non_empty_dir(path: &Path) -> bool {
+    std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+}
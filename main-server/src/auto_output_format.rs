@@ -11,7 +11,7 @@ use axum::{
     response::IntoResponse,
     Form, Json,
 };
-use common::langs::LANGS;
+use common::langs::langs;
 use reqwest::StatusCode;
 use serde::{de::DeserializeOwned, Serialize};
 use tera::{escape_html, to_value, Context, Tera, Value};
@@ -91,7 +91,7 @@ fn get_langs(values: &HashMap<String, Value>) -> Result<Value, tera::Error> {
     if !values.is_empty() {
         return Err(tera::Error::msg("Get langs function takes no arguments"));
     }
-    to_value(LANGS).map_err(tera::Error::json)
+    to_value(&*langs()).map_err(tera::Error::json)
 }
 impl<T: Serialize> AutoOutputFormat<T> {
     pub fn new(data: T, template: &'static str, format: Format) -> Self {
@@ -0,0 +1,55 @@
+use axum::Json;
+use common::langs::{is_valid_version, reload_langs};
+use serde::Deserialize;
+
+use crate::{
+    error::Error,
+    models::account::Account,
+    test_solution::{install_runner_version, reload_runner_langs},
+};
+
+/// Reloads the languages file in both the main server and the lang runner
+pub async fn reload_languages(account: Account) -> Result<&'static str, Error> {
+    if !account.admin {
+        return Err(Error::PermissionDenied(
+            "Only admins can reload the languages",
+        ));
+    }
+
+    reload_langs().map_err(Error::Langs)?;
+    reload_runner_langs().await?;
+
+    Ok("Languages reloaded")
+}
+
+#[derive(Deserialize)]
+pub struct InstallVersion {
+    language: String,
+    version: String,
+}
+
+/// Installs a version of a language on every lang runner, so solutions can use it even if the
+/// languages file doesn't allow it
+pub async fn install_language_version(
+    account: Account,
+    Json(install): Json<InstallVersion>,
+) -> Result<String, Error> {
+    if !account.admin {
+        return Err(Error::PermissionDenied(
+            "Only admins can install language versions",
+        ));
+    }
+    if !is_valid_version(&install.version) {
+        return Err(Error::BadRequest(format!(
+            "{:?} isn't a valid version",
+            install.version
+        )));
+    }
+
+    install_runner_version(&install.language, &install.version).await?;
+
+    Ok(format!(
+        "Installed version {} of {}",
+        install.version, install.language
+    ))
+}
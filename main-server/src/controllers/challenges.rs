@@ -5,6 +5,7 @@ use axum::{
     response::{IntoResponse, Redirect},
     Extension,
 };
+use common::{langs::langs, RunPriority};
 use serde::Serialize;
 use sqlx::PgPool;
 
@@ -16,13 +17,13 @@ use crate::{
         account::Account,
         challenge::{
             Challenge, ChallengeCategory, ChallengeStatus, ChallengeWithAuthorInfo,
-            ChallengeWithTests, NewChallenge, NewOrExistingChallenge,
+            ChallengeWithTests, JudgeScore, NewChallenge, NewOrExistingChallenge,
         },
         solutions::InvalidatedSolution,
     },
     slug::Slug,
     solution_invalidation::notify_challenge_updated,
-    test_solution::test_solution,
+    test_solution::{test_solution, TestRunnerRequest},
 };
 
 #[derive(Serialize)]
@@ -144,12 +145,21 @@ pub async fn new_challenge(
         .into_response());
     }
 
-    let tests = test_solution(
-        &challenge.example_code,
-        "nodejs",
-        "22.4.0",
-        &challenge.judge,
-    )
+    // The example code is JavaScript, run with the version every runner can install
+    let example_version = langs()
+        .get("nodejs")
+        .map(|lang| lang.latest_version.clone())
+        .ok_or(Error::ServerError)?;
+    let tests = test_solution(&TestRunnerRequest {
+        lang: "nodejs",
+        version: &example_version,
+        code: challenge.example_code.as_bytes(),
+        judge: &challenge.judge,
+        judge_lang: &challenge.judge_lang,
+        time_limit: challenge.time_limit,
+        priority: RunPriority::Interactive,
+        benchmark: false,
+    })
     .await
     .inspect_err(|e| eprintln!("{:?}", e))
     .map_err(|_| Error::ServerError)?;
@@ -172,8 +182,8 @@ pub async fn new_challenge(
         None => {
             let row = sqlx::query_scalar!(
                 r#"
-                INSERT INTO challenges (name, judge, description, author, status, category)
-                values ($1, $2, $3, $4, $5::challenge_status, $6::challenge_category)
+                INSERT INTO challenges (name, judge, description, author, status, category, time_limit, judge_lang, judge_score)
+                values ($1, $2, $3, $4, $5::challenge_status, $6::challenge_category, $7, $8, $9::judge_score)
                 RETURNING id"#,
                 challenge.name,
                 challenge.judge,
@@ -181,6 +191,9 @@ pub async fn new_challenge(
                 account.id,
                 challenge.status as ChallengeStatus,
                 challenge.category as ChallengeCategory,
+                challenge.time_limit,
+                challenge.judge_lang,
+                challenge.judge_score as JudgeScore,
             )
             .fetch_one(&pool)
             .await
@@ -207,14 +220,17 @@ pub async fn new_challenge(
 
             if &existing_challenge.challenge.challenge != challenge {
                 sqlx::query!(
-                    r"UPDATE challenges SET name=$1, judge=$2, description=$3, example_code=$4, status=$5::challenge_status, category=$6::challenge_category WHERE id=$7",
+                    r"UPDATE challenges SET name=$1, judge=$2, description=$3, example_code=$4, status=$5::challenge_status, category=$6::challenge_category, time_limit=$7, judge_lang=$9, judge_score=$10::judge_score WHERE id=$8",
                     challenge.name,
                     challenge.judge,
                     challenge.description,
                     challenge.example_code,
                     challenge.status as ChallengeStatus,
                     challenge.category as ChallengeCategory,
-                    id
+                    challenge.time_limit,
+                    id,
+                    challenge.judge_lang,
+                    challenge.judge_score as JudgeScore
                 )
                 .execute(&pool)
                 .await
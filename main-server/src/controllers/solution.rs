@@ -1,32 +1,49 @@
 use axum::{
+    body::Body,
     extract::{Path, Query},
-    http::StatusCode,
+    http::{header, Response, StatusCode},
     response::Redirect,
-    Extension,
+    Extension, Json,
 };
-use common::langs::LANGS;
+use common::{langs::langs, RunLangOutput, RunPriority, RunnerErrorCode};
 use discord_bot::Bot;
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::{query_scalar, types::time::OffsetDateTime, PgPool};
 
 use crate::{
     auto_output_format::{AutoInput, AutoOutputFormat, Format},
     discord::post_updated_score,
-    error::Error,
+    error::{runner_error_message, Error},
     models::{
         account::Account,
         challenge::ChallengeWithAuthorInfo,
         solutions::{Code, LeaderboardEntry, NewSolution, RankingMode},
     },
     slug::Slug,
-    test_case_display::OutputDisplay,
-    test_solution::test_solution,
+    test_case_display::{OutputDisplay, TestCaseDisplay},
+    test_solution::{
+        get_available_versions, runner_score, test_solution, test_solution_streaming, RunProgress,
+        TestRunnerRequest,
+    },
 };
 
 #[derive(Serialize, Deserialize)]
 pub struct SolutionQueryParameters {
     #[serde(default)]
     ranking: RankingMode,
+    /// Only show solutions for this language version on the leaderboard
+    #[serde(default)]
+    version: Option<String>,
+}
+
+impl SolutionQueryParameters {
+    fn version(&self) -> Option<&str> {
+        // An empty value means all versions
+        self.version
+            .as_deref()
+            .filter(|version| !version.is_empty())
+    }
 }
 
 #[derive(Serialize)]
@@ -34,15 +51,22 @@ pub struct AllSolutionsOutput {
     challenge: ChallengeWithAuthorInfo,
     leaderboard: Vec<LeaderboardEntry>,
     tests: Option<OutputDisplay>,
+    /// For the editor, bytes that aren't valid UTF-8 are replaced
     code: Option<String>,
     previous_solution_invalid: bool,
     language: String,
     ranking: RankingMode,
+    /// The version the leaderboard is filtered by
+    version: Option<String>,
+    /// Versions that have a solution on the leaderboard
+    leaderboard_versions: Vec<String>,
+    /// Versions a solution can be submitted with
+    available_versions: Vec<String>,
 }
 
 pub async fn all_solutions(
     Path((challenge_id, _slug, language_name)): Path<(i32, String, String)>,
-    Query(SolutionQueryParameters { ranking }): Query<SolutionQueryParameters>,
+    Query(query): Query<SolutionQueryParameters>,
     format: Format,
     account: Option<Account>,
     Extension(pool): Extension<PgPool>,
@@ -51,11 +75,17 @@ pub async fn all_solutions(
         &pool,
         challenge_id,
         &language_name,
+        query.version(),
         account.as_ref().map(|e| e.id),
-        ranking,
+        query.ranking,
     )
     .await
     .map_err(Error::Database)?;
+    let leaderboard_versions = LeaderboardEntry::get_versions(&pool, challenge_id, &language_name)
+        .await
+        .map_err(Error::Database)?;
+    // The page still works without the runner, solutions just can't pick a version
+    let available_versions = get_available_versions(&language_name).unwrap_or_default();
 
     let challenge = ChallengeWithAuthorInfo::get_by_id(&pool, challenge_id)
         .await?
@@ -73,9 +103,12 @@ pub async fn all_solutions(
             leaderboard,
             tests: None,
             previous_solution_invalid: code.as_ref().is_some_and(|e| !e.valid),
-            code: code.map(|d| d.code),
+            code: code.map(|d| String::from_utf8_lossy(&d.code).into_owned()),
             language: language_name,
-            ranking,
+            ranking: query.ranking,
+            version: query.version().map(str::to_owned),
+            leaderboard_versions,
+            available_versions,
         },
         "challenge.html.jinja",
         format,
@@ -127,52 +160,224 @@ pub async fn challenge_redirect_no_slug(
 
 pub async fn new_solution(
     Path((challenge_id, _slug, language_name)): Path<(i32, String, String)>,
-    Query(SolutionQueryParameters { ranking }): Query<SolutionQueryParameters>,
+    Query(query): Query<SolutionQueryParameters>,
     account: Account,
     Extension(pool): Extension<PgPool>,
     Extension(bot): Extension<Bot>,
     format: Format,
     AutoInput(solution): AutoInput<NewSolution>,
 ) -> Result<AutoOutputFormat<AllSolutionsOutput>, Error> {
-    let version = LANGS
-        .get(&language_name)
-        .ok_or(Error::NotFound)?
-        .latest_version;
+    let submission = Submission::prepare(
+        &pool,
+        &account,
+        challenge_id,
+        language_name,
+        solution,
+        query,
+    )
+    .await?;
 
-    account
-        .save_preferred_language(&pool, &language_name)
-        .await?;
+    let test_result = test_solution(&submission.runner_request()).await?;
 
-    let challenge = ChallengeWithAuthorInfo::get_by_id(&pool, challenge_id)
-        .await?
-        .ok_or(Error::NotFound)?;
+    let (output, status) = submission.save(&pool, bot, account.id, test_result).await?;
 
-    let test_result = test_solution(
-        &solution.code,
-        &language_name,
-        version,
-        &challenge.challenge.challenge.judge,
+    Ok(AutoOutputFormat::new(output, "challenge.html.jinja", format).with_status(status))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+enum SolutionStreamEvent {
+    Queued {
+        position: usize,
+    },
+    TestCase(TestCaseDisplay),
+    Done {
+        status: u16,
+        result: Box<AllSolutionsOutput>,
+    },
+    Error(String),
+}
+
+/// Same as `new_solution`, but responds with newline delimited JSON so test cases can be shown
+/// while the judge is still running. The last event contains the same data `new_solution`
+/// would return.
+pub async fn new_solution_stream(
+    Path((challenge_id, _slug, language_name)): Path<(i32, String, String)>,
+    Query(query): Query<SolutionQueryParameters>,
+    account: Account,
+    Extension(pool): Extension<PgPool>,
+    Extension(bot): Extension<Bot>,
+    Json(solution): Json<NewSolution>,
+) -> Result<Response<Body>, Error> {
+    let submission = Submission::prepare(
+        &pool,
+        &account,
+        challenge_id,
+        language_name,
+        solution,
+        query,
     )
     .await?;
 
-    let previous_code =
-        Code::get_best_code_for_user(&pool, account.id, challenge_id, &language_name).await;
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        let test_result = test_solution_streaming(&submission.runner_request(), |progress| {
+            let _ = sender.send(match progress {
+                RunProgress::Queued { position } => SolutionStreamEvent::Queued { position },
+                RunProgress::TestCase(test_case) => {
+                    SolutionStreamEvent::TestCase(TestCaseDisplay::from_test_case(test_case))
+                }
+            });
+        })
+        .await;
+
+        let result = match test_result {
+            Ok(test_result) => submission.save(&pool, bot, account.id, test_result).await,
+            Err(e) => Err(e),
+        };
+        let event = match result {
+            Ok((result, status)) => SolutionStreamEvent::Done {
+                status: status.as_u16(),
+                result: Box::new(result),
+            },
+            Err(e) => SolutionStreamEvent::Error(stream_error_message(e)),
+        };
+        let _ = sender.send(event);
+    });
+
+    let lines = futures_util::stream::poll_fn(move |cx| receiver.poll_recv(cx)).map(|event| {
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        Ok::<_, serde_json::Error>(line)
+    });
+
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(lines))
+        .unwrap())
+}
+
+/// What to tell users about an error in the stream, the details of server errors only go in the
+/// logs
+fn stream_error_message(error: Error) -> String {
+    match error {
+        Error::RunLang(message) | Error::BadRequest(message) => message,
+        Error::PermissionDenied(message) => message.to_owned(),
+        Error::Runner(e) => {
+            eprintln!("Lang runner error: {e}");
+            runner_error_message(e.code).to_owned()
+        }
+        e => {
+            eprintln!("{e:?}");
+            runner_error_message(RunnerErrorCode::Internal).to_owned()
+        }
+    }
+}
+
+/// A solution that has not been tested yet
+struct Submission {
+    challenge_id: i32,
+    challenge: ChallengeWithAuthorInfo,
+    language_name: String,
+    version: String,
+    available_versions: Vec<String>,
+    code: Vec<u8>,
+    leaderboard: SolutionQueryParameters,
+}
+
+impl Submission {
+    async fn prepare(
+        pool: &PgPool,
+        account: &Account,
+        challenge_id: i32,
+        language_name: String,
+        solution: NewSolution,
+        leaderboard: SolutionQueryParameters,
+    ) -> Result<Self, Error> {
+        let available_versions = get_available_versions(&language_name)?;
+        let version = match solution.version.filter(|version| !version.is_empty()) {
+            Some(version) if !available_versions.contains(&version) => {
+                return Err(Error::BadRequest(format!(
+                    "Version {version} of {language_name} is not available"
+                )))
+            }
+            Some(version) => version,
+            None => available_versions[0].clone(),
+        };
+
+        account
+            .save_preferred_language(pool, &language_name)
+            .await?;
+
+        let challenge = ChallengeWithAuthorInfo::get_by_id(pool, challenge_id)
+            .await?
+            .ok_or(Error::NotFound)?;
+
+        Ok(Submission {
+            challenge_id,
+            challenge,
+            language_name,
+            version,
+            available_versions,
+            code: solution.code,
+            leaderboard,
+        })
+    }
+
+    fn runner_request(&self) -> TestRunnerRequest<'_> {
+        let challenge = &self.challenge.challenge.challenge;
+        TestRunnerRequest {
+            lang: &self.language_name,
+            version: &self.version,
+            code: &self.code,
+            judge: &challenge.judge,
+            judge_lang: &challenge.judge_lang,
+            time_limit: challenge.time_limit,
+            priority: RunPriority::Interactive,
+            benchmark: challenge.category.is_scored_by_runtime(),
+        }
+    }
 
-    let previous_solution_invalid =
-        !test_result.tests.pass && previous_code.as_ref().is_some_and(|e| !e.valid);
+    /// Saves the solution if it passed and is better than the previous one
+    async fn save(
+        self,
+        pool: &PgPool,
+        bot: Bot,
+        account_id: i32,
+        test_result: RunLangOutput,
+    ) -> Result<(AllSolutionsOutput, StatusCode), Error> {
+        let Submission {
+            challenge_id,
+            challenge,
+            language_name,
+            version,
+            available_versions,
+            code,
+            leaderboard,
+        } = self;
 
-    let status = if test_result.tests.pass {
-        // Currently the web browser turns all line breaks into "\r\n" when a solution
-        // is submitted. This should eventually be fixed in the frontend, but for now
-        // we just replace "\r\n" with "\n" when calculating the score to make it match
-        // the byte counter in the editor.
-        // Related: https://github.com/mousetail/Byte-Heist/issues/34
-        let new_score = (solution.code.len() - solution.code.matches("\r\n").count()) as i32;
+        let previous_code =
+            Code::get_best_code_for_user(pool, account_id, challenge_id, &language_name).await;
 
-        match previous_code {
-            None => {
-                sqlx::query!(
-                    "INSERT INTO solutions (
+        let previous_solution_invalid =
+            !test_result.tests.pass && previous_code.as_ref().is_some_and(|e| !e.valid);
+        let judge_score = challenge.challenge.challenge.judge_score;
+
+        let status = if test_result.tests.pass {
+            let new_score = match runner_score(
+                challenge.challenge.challenge.category,
+                judge_score,
+                &test_result,
+            ) {
+                Some(score) => score?,
+                None => score_code(&language_name, &code)? as f64,
+            };
+
+            match previous_code {
+                None => {
+                    sqlx::query!(
+                        "INSERT INTO solutions (
                         language,
                         version,
                         challenge, 
@@ -181,92 +386,136 @@ pub async fn new_solution(
                         score, 
                         last_improved_date
                     ) values ($1, $2, $3, $4, $5, $6, $7)",
-                    language_name,
-                    version,
-                    challenge_id,
-                    solution.code,
-                    account.id,
-                    new_score,
-                    OffsetDateTime::now_utc()
-                )
-                .execute(&pool)
-                .await
-                .map_err(Error::Database)?;
+                        language_name,
+                        version,
+                        challenge_id,
+                        code,
+                        account_id,
+                        new_score,
+                        OffsetDateTime::now_utc()
+                    )
+                    .execute(pool)
+                    .await
+                    .map_err(Error::Database)?;
 
-                tokio::spawn(
-                    post_updated_score(pool.clone(), bot, challenge_id, account.id, language_name.clone(), new_score, challenge.challenge.challenge.status)
-                );
+                    tokio::spawn(
+                        post_updated_score(pool.clone(), bot, challenge_id, account_id, language_name.clone(), new_score, challenge.challenge.challenge.status)
+                    );
 
-                StatusCode::CREATED
-            }
-            Some(w) if
-                // Always replace an invalid solution
-                !w.valid
-                // Replace a solution if the score is better
-                || w.score >= new_score => {
-                sqlx::query!(
-                    "UPDATE solutions SET 
+                    StatusCode::CREATED
+                }
+                Some(w) if
+                    // Always replace an invalid solution
+                    !w.valid
+                    // Replace a solution if the score is better
+                    || judge_score.is_at_least_as_good(new_score, w.score) => {
+                    sqlx::query!(
+                        "UPDATE solutions SET 
                         code=$1,
                         score=$2,
                         valid=true,
                         validated_at=now(),
-                        last_improved_date=$3
+                        last_improved_date=$3,
+                        version=$5
                     WHERE id=$4",
-                    solution.code,
-                    new_score,
-                    if new_score < w.score || !w.valid {
-                        OffsetDateTime::now_utc()
-                    } else {
-                        w.last_improved_date
-                    },
-                    w.id
-                )
-                .execute(&pool)
-                .await
-                .map_err(Error::Database)?;
+                        code,
+                        new_score,
+                        if judge_score.is_better(new_score, w.score) || !w.valid {
+                            OffsetDateTime::now_utc()
+                        } else {
+                            w.last_improved_date
+                        },
+                        w.id,
+                        version
+                    )
+                    .execute(pool)
+                    .await
+                    .map_err(Error::Database)?;
 
-                tokio::spawn(
-                    post_updated_score(pool.clone(), bot, challenge_id, account.id, language_name.clone(), new_score, challenge.challenge.challenge.status)
-                );
+                    tokio::spawn(
+                        post_updated_score(pool.clone(), bot, challenge_id, account_id, language_name.clone(), new_score, challenge.challenge.challenge.status)
+                    );
 
-                StatusCode::CREATED
+                    StatusCode::CREATED
+                }
+                Some(_) => {
+                    // This means the code passed but is not better than the previously saved solution
+                    // So we don't save
+                    StatusCode::OK
+                },
             }
-            Some(_) => {
-                // This means the code passed but is not better than the previously saved solution
-                // So we don't save
-                StatusCode::OK
+        } else {
+            StatusCode::BAD_REQUEST
+        };
+
+        Ok((
+            AllSolutionsOutput {
+                challenge,
+                leaderboard: LeaderboardEntry::get_leaderboard_near(
+                    pool,
+                    challenge_id,
+                    &language_name,
+                    leaderboard.version(),
+                    Some(account_id),
+                    leaderboard.ranking,
+                )
+                .await
+                .map_err(Error::Database)?,
+                tests: Some(test_result.into()),
+                code: Some(String::from_utf8_lossy(&code).into_owned()),
+                previous_solution_invalid,
+                ranking: leaderboard.ranking,
+                version: leaderboard.version().map(str::to_owned),
+                leaderboard_versions: LeaderboardEntry::get_versions(
+                    pool,
+                    challenge_id,
+                    &language_name,
+                )
+                .await
+                .map_err(Error::Database)?,
+                available_versions,
+                language: language_name,
             },
-        }
-    } else {
-        StatusCode::BAD_REQUEST
+            status,
+        ))
+    }
+}
+
+/// Scores code the same way as submitted solutions
+pub fn score_code(language_name: &str, code: &[u8]) -> Result<usize, Error> {
+    let scoring = langs().get(language_name).ok_or(Error::NotFound)?.scoring;
+
+    // Binary code can't be scored in characters
+    let Ok(code) = std::str::from_utf8(code) else {
+        return Ok(code.len());
     };
-    Ok(AutoOutputFormat::new(
-        AllSolutionsOutput {
-            challenge,
-            leaderboard: LeaderboardEntry::get_leaderboard_near(
-                &pool,
-                challenge_id,
-                &language_name,
-                Some(account.id),
-                ranking,
-            )
-            .await
-            .map_err(Error::Database)?,
-            tests: Some(test_result.into()),
-            code: Some(solution.code),
-            language: language_name,
-            previous_solution_invalid,
-            ranking,
-        },
-        "challenge.html.jinja",
-        format,
-    )
-    .with_status(status))
+
+    // Currently the web browser turns all line breaks into "\r\n" when a solution
+    // is submitted. This should eventually be fixed in the frontend, but for now
+    // we just replace "\r\n" with "\n" when calculating the score to make it match
+    // the byte counter in the editor.
+    // Related: https://github.com/mousetail/Byte-Heist/issues/34
+    Ok(scoring.score(&code.replace("\r\n", "\n")))
+}
+
+#[derive(Serialize)]
+pub struct ScoreOutput {
+    score: usize,
+}
+
+/// Lets the editor show the exact score a solution would get
+pub async fn score_solution(
+    Path(language_name): Path<String>,
+    Json(solution): Json<NewSolution>,
+) -> Result<Json<ScoreOutput>, Error> {
+    Ok(Json(ScoreOutput {
+        score: score_code(&language_name, &solution.code)?,
+    }))
 }
 
 pub async fn get_leaderboard(
     Path((challenge_id, _slug, language_name)): Path<(i32, String, String)>,
-    Query(SolutionQueryParameters { ranking }): Query<SolutionQueryParameters>,
+    Query(query): Query<SolutionQueryParameters>,
     account: Account,
     Extension(pool): Extension<PgPool>,
     format: Format,
@@ -275,8 +524,9 @@ pub async fn get_leaderboard(
         &pool,
         challenge_id,
         &language_name,
+        query.version(),
         Some(account.id),
-        ranking,
+        query.ranking,
     )
     .await
     .map_err(Error::Database)?;
@@ -5,13 +5,17 @@ use sqlx::{query_as, query_scalar, PgPool};
 use crate::{
     auto_output_format::{AutoOutputFormat, Format},
     error::Error,
-    models::{account::Account, solutions::InvalidatedSolution},
+    models::{
+        account::Account,
+        solutions::{serialize_score, InvalidatedSolution},
+    },
 };
 
 #[derive(Serialize)]
 pub struct UserPageLeaderboardEntry {
     language: String,
-    score: i32,
+    #[serde(serialize_with = "serialize_score")]
+    score: f64,
     challenge_id: i32,
     challenge_name: String,
 }
@@ -120,7 +120,7 @@ pub async fn post_updated_score(
     challenge_id: i32,
     author: i32,
     language: String,
-    score: i32,
+    score: f64,
     status: ChallengeStatus,
 ) {
     match status {
@@ -1,4 +1,5 @@
 use axum::{body::Body, http::Response, response::IntoResponse};
+use common::{langs::LangsError, RunnerError, RunnerErrorCode};
 use reqwest::StatusCode;
 
 #[derive(Debug)]
@@ -9,7 +10,10 @@ pub enum Error {
     Database(sqlx::Error),
     Oauth(OauthError),
     RunLang(String),
+    Runner(RunnerError),
     PermissionDenied(&'static str),
+    BadRequest(String),
+    Langs(LangsError),
 }
 
 #[derive(Debug)]
@@ -30,6 +34,39 @@ impl IntoResponse for OauthError {
     }
 }
 
+/// What to tell users when the lang runner fails, the details only go in the logs
+pub fn runner_error_message(code: RunnerErrorCode) -> &'static str {
+    match code {
+        RunnerErrorCode::UnknownLanguage => "This language isn't available on the lang runner.",
+        RunnerErrorCode::VersionNotAllowed => "This version of the language isn't available.",
+        RunnerErrorCode::InvalidVersion => "This isn't a valid version name.",
+        RunnerErrorCode::ToolchainBusy => {
+            "This version of the language is being changed, please try again in a moment."
+        }
+        RunnerErrorCode::NotInstalled => "This version of the language isn't installed.",
+        RunnerErrorCode::UninstallFailed => "The language couldn't be uninstalled.",
+        RunnerErrorCode::NotAJudgeLanguage => {
+            "The judge of this challenge is written in a language that can't run judges."
+        }
+        RunnerErrorCode::InstallFailed => {
+            "The language couldn't be installed, please try again later."
+        }
+        RunnerErrorCode::SandboxSpawnFailed => {
+            "Your code couldn't be started, please try again later."
+        }
+        RunnerErrorCode::SemaphoreClosed => {
+            "The lang runner is restarting, please try again in a moment."
+        }
+        RunnerErrorCode::Unauthorized => {
+            "The lang runner doesn't accept requests from this server, please tell an admin."
+        }
+        RunnerErrorCode::BodyTooLarge => "Your code is too long to run.",
+        RunnerErrorCode::Internal | RunnerErrorCode::Unknown => {
+            "Something went wrong while running your code, please try again later."
+        }
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         match self {
@@ -60,6 +97,24 @@ impl IntoResponse for Error {
                     tera::escape_html(&s)
                 )))
                 .unwrap(),
+            Error::Runner(e) => {
+                eprintln!("Lang runner error: {e}");
+                let status = match e.code {
+                    RunnerErrorCode::UnknownLanguage
+                    | RunnerErrorCode::VersionNotAllowed
+                    | RunnerErrorCode::InvalidVersion => StatusCode::BAD_REQUEST,
+                    RunnerErrorCode::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+                    _ if e.retryable => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                Response::builder()
+                    .status(status)
+                    .body(Body::from(format!(
+                        "<h2>Lang Runner Error</h2><p>{}</p>",
+                        tera::escape_html(runner_error_message(e.code))
+                    )))
+                    .unwrap()
+            }
             Error::PermissionDenied(e) => Response::builder()
                 .status(StatusCode::FORBIDDEN)
                 .body(Body::from(format!(
@@ -68,6 +123,20 @@ impl IntoResponse for Error {
                     tera::escape_html(e)
                 )))
                 .unwrap(),
+            Error::BadRequest(e) => Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!(
+                    "<h2>Bad Request</h2><p>{}</p>",
+                    tera::escape_html(&e)
+                )))
+                .unwrap(),
+            Error::Langs(e) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from(format!(
+                    "<h2>Invalid Languages File</h2><pre>{}</pre>",
+                    tera::escape_html(&e.to_string())
+                )))
+                .unwrap(),
         }
     }
 }
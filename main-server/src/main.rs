@@ -4,6 +4,7 @@ mod discord;
 mod error;
 mod markdown;
 mod models;
+mod runners;
 mod slug;
 mod solution_invalidation;
 mod strip_trailing_slashes;
@@ -11,15 +12,20 @@ mod test_case_display;
 mod test_solution;
 mod vite;
 
-use axum::{routing::get, Extension, Router};
+use axum::{
+    routing::{get, post},
+    Extension, Router,
+};
 
 use anyhow::Context;
 use controllers::{
+    admin::{install_language_version, reload_languages},
     auth::{github_callback, github_login},
     challenges::{all_challenges, compose_challenge, new_challenge, view_challenge},
     solution::{
         all_solutions, challenge_redirect, challenge_redirect_no_slug,
-        challenge_redirect_with_slug, get_leaderboard, new_solution,
+        challenge_redirect_with_slug, get_leaderboard, new_solution, new_solution_stream,
+        score_solution,
     },
     user::get_user,
 };
@@ -49,6 +55,11 @@ async fn main() -> anyhow::Result<()> {
         // .with_span_events(FmtSpan::FULL)
         .init();
 
+    // Fail early if the languages file is invalid
+    common::langs::langs();
+    std::sync::LazyLock::force(&runners::RUNNER_SECRET);
+    std::sync::LazyLock::force(&runners::RUNNERS);
+
     // Setup SQLX
     let pool = PgPoolOptions::new()
         .max_connections(50)
@@ -70,6 +81,7 @@ async fn main() -> anyhow::Result<()> {
     );
 
     let _invalidation_task = tokio::task::spawn(solution_invalidation_task(pool.clone()));
+    let _runner_health_task = tokio::task::spawn(runners::runner_health_task());
 
     // Bot
     let bot = if let Some((token, channel_id)) = std::env::var("DISCORD_TOKEN")
@@ -112,9 +124,16 @@ async fn main() -> anyhow::Result<()> {
             "/challenge/:id/:slug/solve/:language",
             get(all_solutions).post(new_solution),
         )
+        .route(
+            "/challenge/:id/:slug/solve/:language/stream",
+            post(new_solution_stream),
+        )
+        .route("/lang/:language/score", post(score_solution))
         .route("/login/github", get(github_login))
         .route("/callback/github", get(github_callback))
         .route("/user/:id", get(get_user))
+        .route("/admin/reload-languages", post(reload_languages))
+        .route("/admin/install-version", post(install_language_version))
         .route("/:id/:language", get(challenge_redirect_no_slug))
         .nest_service("/static", ServeDir::new("static"))
         .fallback(get(strip_trailing_slashes))
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use common::langs::langs;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
@@ -30,6 +31,51 @@ pub enum ChallengeCategory {
     CodeGolf,
     RestrictedSource,
     Private,
+    /// Scored by how long the code takes to run instead of by its length
+    FastestCode,
+}
+
+impl ChallengeCategory {
+    pub fn is_scored_by_runtime(self) -> bool {
+        self == ChallengeCategory::FastestCode
+    }
+}
+
+/// Whether solutions are scored by the score their judge reports, and which way the scores sort
+#[derive(Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+#[derive(sqlx::Type)]
+#[sqlx(type_name = "judge_score", rename_all = "kebab-case")]
+pub enum JudgeScore {
+    /// Solutions are scored by their length, or by their runtime for fastest code challenges
+    #[default]
+    Unused,
+    LowerIsBetter,
+    HigherIsBetter,
+}
+
+impl JudgeScore {
+    pub fn is_better(self, new_score: f64, old_score: f64) -> bool {
+        match self {
+            JudgeScore::HigherIsBetter => new_score > old_score,
+            JudgeScore::Unused | JudgeScore::LowerIsBetter => new_score < old_score,
+        }
+    }
+
+    pub fn is_at_least_as_good(self, new_score: f64, old_score: f64) -> bool {
+        new_score == old_score || self.is_better(new_score, old_score)
+    }
+}
+
+pub const DEFAULT_TIME_LIMIT: i32 = 3;
+const MAX_TIME_LIMIT: i32 = 30;
+
+fn default_time_limit() -> i32 {
+    DEFAULT_TIME_LIMIT
+}
+
+fn default_judge_lang() -> String {
+    "deno".to_owned()
 }
 
 #[derive(sqlx::FromRow, Deserialize, Serialize, Eq, PartialEq, Clone)]
@@ -40,6 +86,14 @@ pub struct NewChallenge {
     pub example_code: String,
     pub category: ChallengeCategory,
     pub status: ChallengeStatus,
+    /// Time limit in seconds, individual languages may get more time
+    #[serde(default = "default_time_limit")]
+    pub time_limit: i32,
+    /// The language the judge is written in
+    #[serde(default = "default_judge_lang")]
+    pub judge_lang: String,
+    #[serde(default)]
+    pub judge_score: JudgeScore,
 }
 
 impl NewChallenge {
@@ -55,6 +109,21 @@ impl NewChallenge {
         if self.description.is_empty() {
             errors.insert("description", "description can not be empty");
         }
+        if !(1..=MAX_TIME_LIMIT).contains(&self.time_limit) {
+            errors.insert("time_limit", "time limit must be between 1 and 30 seconds");
+        }
+        if langs()
+            .get(&self.judge_lang)
+            .is_none_or(|lang| lang.judge_runner.is_none())
+        {
+            errors.insert("judge_lang", "judges can't be written in this language");
+        }
+        if self.category.is_scored_by_runtime() && self.judge_score != JudgeScore::Unused {
+            errors.insert(
+                "judge_score",
+                "fastest code challenges are always scored by runtime",
+            );
+        }
         if self.status == ChallengeStatus::Public
             && !is_admin
             && previous.is_none_or(|k| k.status == ChallengeStatus::Public)
@@ -116,6 +185,9 @@ impl Default for NewChallenge {
             example_code: String::new(),
             category: ChallengeCategory::RestrictedSource,
             status: ChallengeStatus::Draft,
+            time_limit: DEFAULT_TIME_LIMIT,
+            judge_lang: default_judge_lang(),
+            judge_score: JudgeScore::Unused,
         }
     }
 }
@@ -184,6 +256,9 @@ impl ChallengeWithAuthorInfo {
             challenges.author,
             challenges.category,
             challenges.status,
+            challenges.time_limit,
+            challenges.judge_lang,
+            challenges.judge_score,
             accounts.username as author_name,
             accounts.avatar as author_avatar
             FROM challenges LEFT JOIN accounts ON challenges.author = accounts.id
@@ -1,10 +1,72 @@
-use serde::{Deserialize, Serialize};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize, Serializer};
 use sqlx::{query_as, query_scalar, PgPool};
 use tower_sessions::cookie::time::OffsetDateTime;
 
-#[derive(sqlx::FromRow, Deserialize, Serialize)]
+#[derive(sqlx::FromRow, Deserialize, Serialize, Clone)]
+#[serde(try_from = "NewSolutionInput", into = "NewSolutionInput")]
 pub struct NewSolution {
-    pub code: String,
+    pub code: Vec<u8>,
+    /// The language version to run the code with, the latest version if not set
+    #[sqlx(skip)]
+    pub version: Option<String>,
+}
+
+/// How the code of a submitted solution is encoded
+#[derive(Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeEncoding {
+    /// Plain text, like the code textarea of a form
+    #[default]
+    Utf8,
+    /// For code that isn't valid UTF-8
+    Base64,
+}
+
+/// A solution as it's submitted, the code is only decoded from base64 when `encoding` says so
+#[derive(Deserialize, Serialize)]
+struct NewSolutionInput {
+    code: String,
+    #[serde(default)]
+    encoding: CodeEncoding,
+    #[serde(default)]
+    version: Option<String>,
+}
+
+impl TryFrom<NewSolutionInput> for NewSolution {
+    type Error = String;
+
+    fn try_from(input: NewSolutionInput) -> Result<Self, Self::Error> {
+        let code = match input.encoding {
+            CodeEncoding::Utf8 => input.code.into_bytes(),
+            CodeEncoding::Base64 => STANDARD
+                .decode(input.code)
+                .map_err(|e| format!("The code isn't valid base64: {e}"))?,
+        };
+        Ok(NewSolution {
+            code,
+            version: input.version,
+        })
+    }
+}
+
+impl From<NewSolution> for NewSolutionInput {
+    fn from(solution: NewSolution) -> Self {
+        NewSolutionInput {
+            code: STANDARD.encode(solution.code),
+            encoding: CodeEncoding::Base64,
+            version: solution.version,
+        }
+    }
+}
+
+/// Serializes whole scores as integers, so code lengths don't show up as `123.0`
+pub fn serialize_score<S: Serializer>(score: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if score.fract() == 0.0 && score.abs() < i64::MAX as f64 {
+        serializer.serialize_i64(*score as i64)
+    } else {
+        serializer.serialize_f64(*score)
+    }
 }
 
 #[derive(sqlx::FromRow, Deserialize, Serialize)]
@@ -16,13 +78,16 @@ pub struct Solution {
     #[sqlx(flatten)]
     pub solution: NewSolution,
     pub author: i32,
-    pub score: i32,
+    #[serde(serialize_with = "serialize_score")]
+    pub score: f64,
 }
 
 #[derive(Serialize)]
 pub struct Code {
-    pub code: String,
-    pub score: i32,
+    #[serde(with = "common::bytes::base64")]
+    pub code: Vec<u8>,
+    #[serde(serialize_with = "serialize_score")]
+    pub score: f64,
     pub id: i32,
     pub valid: bool,
     pub last_improved_date: OffsetDateTime,
@@ -60,7 +125,9 @@ pub struct LeaderboardEntry {
     pub author_id: i32,
     pub author_name: String,
     pub author_avatar: String,
-    pub score: i32,
+    #[serde(serialize_with = "serialize_score")]
+    pub score: f64,
+    pub version: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -81,11 +148,13 @@ impl LeaderboardEntry {
         pool: &PgPool,
         challenge_id: i32,
         language: &str,
+        version: Option<&str>,
         user_id: Option<i32>,
         mode: RankingMode,
     ) -> Result<Vec<Self>, sqlx::Error> {
         let mut leaderboard =
-            Self::get_leadeboard_for_challenge_and_language(pool, challenge_id, language).await?;
+            Self::get_leadeboard_for_challenge_and_language(pool, challenge_id, language, version)
+                .await?;
 
         match mode {
             RankingMode::Top => {
@@ -123,11 +192,15 @@ impl LeaderboardEntry {
                 accounts.username as author_name,
                 accounts.avatar as author_avatar,
                 1 as "rank!",
-                score
+                score,
+                solutions.version
             FROM solutions
                 LEFT JOIN accounts ON solutions.author = accounts.id
+                LEFT JOIN challenges ON solutions.challenge = challenges.id
             WHERE solutions.challenge=$1 AND solutions.language=$2 AND valid=true
-            ORDER BY solutions.score ASC, last_improved_date ASC
+            ORDER BY
+                CASE WHEN challenges.judge_score = 'higher-is-better' THEN -solutions.score ELSE solutions.score END ASC,
+                last_improved_date ASC
             LIMIT 1
             "#,
             challenge_id,
@@ -137,10 +210,12 @@ impl LeaderboardEntry {
         .await
     }
 
+    /// Only includes solutions for `version` if it is set
     pub async fn get_leadeboard_for_challenge_and_language(
         pool: &PgPool,
         challenge_id: i32,
         language: &str,
+        version: Option<&str>,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as!(
             LeaderboardEntry,
@@ -151,13 +226,38 @@ impl LeaderboardEntry {
                 accounts.username as author_name,
                 accounts.avatar as author_avatar,
                 score,
-                rank() OVER (ORDER BY solutions.score ASC) as "rank!"
+                solutions.version,
+                rank() OVER (ORDER BY
+                    CASE WHEN challenges.judge_score = 'higher-is-better' THEN -solutions.score ELSE solutions.score END ASC
+                ) as "rank!"
             FROM solutions
                 LEFT JOIN accounts ON solutions.author = accounts.id
+                LEFT JOIN challenges ON solutions.challenge = challenges.id
             WHERE solutions.challenge=$1 AND solutions.language=$2 AND valid=true
-            ORDER BY solutions.score ASC, last_improved_date ASC
+                AND ($3::varchar IS NULL OR solutions.version=$3)
+            ORDER BY
+                CASE WHEN challenges.judge_score = 'higher-is-better' THEN -solutions.score ELSE solutions.score END ASC,
+                last_improved_date ASC
             "#,
             challenge_id,
+            language,
+            version
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// All versions that have a valid solution, to split the leaderboard by
+    pub async fn get_versions(
+        pool: &PgPool,
+        challenge_id: i32,
+        language: &str,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        query_scalar!(
+            "SELECT DISTINCT version FROM solutions
+            WHERE challenge=$1 AND language=$2 AND valid=true
+            ORDER BY version",
+            challenge_id,
             language
         )
         .fetch_all(pool)
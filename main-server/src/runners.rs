@@ -0,0 +1,208 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        LazyLock, RwLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use common::signature::{sign, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+use reqwest::Method;
+use tokio::time::sleep;
+
+/// How often every runner is checked
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The secret shared with the lang runners, which reject requests that aren't signed with it
+pub static RUNNER_SECRET: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("YQ_RUNNER_SECRET")
+        .ok()
+        .filter(|secret| !secret.is_empty())
+        .expect("Missing the YQ_RUNNER_SECRET environment variable")
+});
+
+/// The lang runners, from `YQ_RUNNER_URLS` separated by commas
+pub static RUNNERS: LazyLock<Vec<Runner>> = LazyLock::new(|| {
+    let runners: Vec<Runner> = std::env::var("YQ_RUNNER_URLS")
+        .unwrap_or_else(|_| "http://localhost:3000".to_owned())
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(|url| Runner::new(url.trim_end_matches('/')))
+        .collect();
+    assert!(
+        !runners.is_empty(),
+        "YQ_RUNNER_URLS doesn't contain any URLs"
+    );
+    runners
+});
+
+struct RunnerStatus {
+    healthy: bool,
+    /// Installed versions by plugin name
+    installed: HashMap<String, BTreeSet<String>>,
+}
+
+/// A lang runner jobs can be sent to
+pub struct Runner {
+    pub url: String,
+    /// Jobs sent to this runner that haven't finished yet
+    jobs: AtomicUsize,
+    status: RwLock<RunnerStatus>,
+}
+
+/// Counts as a job of the runner until it's dropped
+pub struct Job<'a>(&'a Runner);
+
+impl Drop for Job<'_> {
+    fn drop(&mut self) {
+        self.0.jobs.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Runner {
+    fn new(url: &str) -> Self {
+        Runner {
+            url: url.to_owned(),
+            jobs: AtomicUsize::new(0),
+            // Runners get jobs before their first health check
+            status: RwLock::new(RunnerStatus {
+                healthy: true,
+                installed: HashMap::new(),
+            }),
+        }
+    }
+
+    /// A request to the runner, signed with the shared secret
+    pub fn request(&self, method: Method, path: &str, body: Vec<u8>) -> reqwest::RequestBuilder {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("The clock is after 1970")
+            .as_secs();
+        let signature = sign(&RUNNER_SECRET, timestamp, method.as_str(), path, &body);
+        reqwest::Client::new()
+            .request(method, format!("{}{path}", self.url))
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(SIGNATURE_HEADER, signature)
+            .body(body)
+    }
+
+    pub fn start_job(&self) -> Job<'_> {
+        self.jobs.fetch_add(1, Ordering::Relaxed);
+        Job(self)
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.status.read().unwrap().healthy
+    }
+
+    fn has_installed(&self, plugin_name: &str, version: &str) -> bool {
+        self.status
+            .read()
+            .unwrap()
+            .installed
+            .get(plugin_name)
+            .is_some_and(|versions| versions.contains(version))
+    }
+
+    /// The runner couldn't be reached, so it gets no jobs until the next health check passes
+    pub fn mark_unhealthy(&self) {
+        let mut status = self.status.write().unwrap();
+        if status.healthy {
+            eprintln!("Lang runner {} is unreachable", self.url);
+            status.healthy = false;
+        }
+    }
+
+    /// The runner ran a job in this version, so it installed it if it hadn't already
+    pub fn mark_installed(&self, plugin_name: &str, version: &str) {
+        self.status
+            .write()
+            .unwrap()
+            .installed
+            .entry(plugin_name.to_owned())
+            .or_default()
+            .insert(version.to_owned());
+    }
+
+    /// Checks that the runner responds, and which versions it has installed
+    async fn check_health(&self) {
+        let check = async {
+            self.request(Method::GET, "/", vec![])
+                .timeout(Duration::from_secs(5))
+                .send()
+                .await?
+                .error_for_status()?;
+            self.request(Method::GET, "/lang-versions", vec![])
+                .timeout(Duration::from_secs(5))
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<HashMap<String, HashMap<String, ()>>>()
+                .await
+        };
+
+        match check.await {
+            Ok(installed) => {
+                let mut status = self.status.write().unwrap();
+                if !status.healthy {
+                    eprintln!("Lang runner {} is healthy again", self.url);
+                }
+                status.healthy = true;
+                status.installed = installed
+                    .into_iter()
+                    .map(|(plugin_name, versions)| (plugin_name, versions.into_keys().collect()))
+                    .collect();
+            }
+            Err(e) => {
+                let mut status = self.status.write().unwrap();
+                if status.healthy {
+                    eprintln!("Lang runner {} failed its health check: {e}", self.url);
+                }
+                status.healthy = false;
+            }
+        }
+    }
+}
+
+/// The runners to try for a job in order: healthy runners before the others, then the ones that
+/// have the version installed, then the ones with the fewest jobs. Unhealthy runners are still
+/// tried last, since they may have recovered since the last health check.
+pub fn runners_for(plugin_name: &str, version: &str) -> Vec<&'static Runner> {
+    let mut runners: Vec<&Runner> = RUNNERS.iter().collect();
+    runners.sort_by_cached_key(|runner| {
+        (
+            !runner.is_healthy(),
+            !runner.has_installed(plugin_name, version),
+            runner.jobs.load(Ordering::Relaxed),
+        )
+    });
+    runners
+}
+
+/// The versions of a plugin installed on any healthy runner
+pub fn installed_versions(plugin_name: &str) -> BTreeSet<String> {
+    RUNNERS
+        .iter()
+        .filter(|runner| runner.is_healthy())
+        .flat_map(|runner| {
+            runner
+                .status
+                .read()
+                .unwrap()
+                .installed
+                .get(plugin_name)
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Checks every runner in the background, so jobs go to the runners that are up
+pub async fn runner_health_task() {
+    loop {
+        futures_util::future::join_all(RUNNERS.iter().map(Runner::check_health)).await;
+        sleep(HEALTH_CHECK_INTERVAL).await;
+    }
+}
@@ -1,18 +1,28 @@
 use std::time::Duration;
 
-use common::langs::LANGS;
+use common::RunPriority;
 use futures_util::StreamExt;
 use sqlx::{query, query_as, PgPool};
 use tokio::time::sleep;
 use tower_sessions::cookie::time::OffsetDateTime;
 
-use crate::test_solution::test_solution;
+use crate::{
+    controllers::solution::score_code,
+    error::Error,
+    models::challenge::{ChallengeCategory, JudgeScore},
+    test_solution::{runner_score, test_solution, TestRunnerRequest},
+};
 
 struct QueueEntry {
     id: i32,
-    code: String,
+    code: Vec<u8>,
     language: String,
+    version: String,
     judge: String,
+    judge_lang: String,
+    time_limit: i32,
+    category: ChallengeCategory,
+    judge_score: JudgeScore,
 }
 
 static SOLUTION_INVALIATION_NOTIFICATION: tokio::sync::Notify = tokio::sync::Notify::const_new();
@@ -22,7 +32,7 @@ pub async fn solution_invalidation_task(pool: PgPool) {
         let mut tasks = query_as!(
             QueueEntry,
             r#"
-                SELECT solutions.id, solutions.code as code, challenges.judge as judge, solutions.language as language
+                SELECT solutions.id, solutions.code as code, challenges.judge as judge, challenges.judge_lang as judge_lang, solutions.language as language, solutions.version as version, challenges.time_limit as time_limit, challenges.category as "category!: ChallengeCategory", challenges.judge_score as "judge_score!: JudgeScore"
                 FROM solutions
                 LEFT JOIN challenges ON solutions.challenge = challenges.id
                 WHERE challenges.updated_at > solutions.validated_at
@@ -41,11 +51,24 @@ pub async fn solution_invalidation_task(pool: PgPool) {
                 }
             };
 
-            let version = LANGS.get(&task.language).unwrap().latest_version;
-
-            let result = match test_solution(&task.code, &task.language, version, &task.judge).await
+            // Re-test with the version the solution was submitted with, so a newer
+            // version can't break it. Solutions scored by runtime or by their judge get a new score.
+            let result = match test_solution(&TestRunnerRequest {
+                lang: &task.language,
+                version: &task.version,
+                code: &task.code,
+                judge: &task.judge,
+                judge_lang: &task.judge_lang,
+                time_limit: task.time_limit,
+                priority: RunPriority::Revalidation,
+                benchmark: task.category.is_scored_by_runtime(),
+            })
+            .await
             {
-                Ok(e) => e,
+                Ok(result) => Ok(result),
+                // The language or version isn't allowed anymore, or the judge can't run at all,
+                // trying again won't change that
+                Err(Error::Runner(err)) if !err.retryable => Err(Error::Runner(err)),
                 Err(err) => {
                     eprintln!("{err:?}");
 
@@ -54,32 +77,56 @@ pub async fn solution_invalidation_task(pool: PgPool) {
                 }
             };
 
-            if result.tests.pass {
-                query!(
-                    "UPDATE solutions SET validated_at=now() WHERE id=$1",
-                    task.id
-                )
-                .execute(&pool)
-                .await
-                .unwrap();
-            } else {
-                println!(
-                    "Solution {} invalidated at {}",
-                    task.id,
-                    OffsetDateTime::now_utc()
-                );
+            // Code length scores are recomputed too, in case the scoring of the language changed
+            let new_score = result.and_then(|result| {
+                if !result.tests.pass {
+                    return Ok(None);
+                }
+                match runner_score(task.category, task.judge_score, &result) {
+                    Some(score) => score.map(Some),
+                    None => score_code(&task.language, &task.code).map(|score| Some(score as f64)),
+                }
+            });
+            // Solutions that can't run anymore, or pass without a score to rank them by, are invalid
+            // like failing ones
+            let pass = match new_score {
+                Ok(Some(score)) => {
+                    query!(
+                        "UPDATE solutions SET validated_at=now(), score=$2 WHERE id=$1",
+                        task.id,
+                        score
+                    )
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                    true
+                }
+                new_score => {
+                    if let Err(err) = new_score {
+                        eprintln!("Solution {} can't be run or scored: {err:?}", task.id);
+                    }
+                    println!(
+                        "Solution {} invalidated at {}",
+                        task.id,
+                        OffsetDateTime::now_utc()
+                    );
 
-                query!("UPDATE solutions SET valid=false WHERE id=$1", task.id)
+                    query!(
+                        "UPDATE solutions SET valid=false, validated_at=now() WHERE id=$1",
+                        task.id
+                    )
                     .execute(&pool)
                     .await
                     .unwrap();
-            }
+                    false
+                }
+            };
 
             query!(
                 "INSERT INTO solution_invalidation_log(solution, pass)
                 VALUES ($1, $2)",
                 task.id,
-                result.tests.pass
+                pass
             )
             .execute(&pool)
             .await
@@ -1,6 +1,9 @@
 use std::borrow::Cow;
 
-use common::{RunLangOutput, TestCase, TestPassState};
+use common::{
+    bytes::ByteString, InputFile, LimitExceeded, ResourceUsage, RunLangOutput, TestCase,
+    TestPassState,
+};
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -9,6 +12,8 @@ pub struct TestCaseDisplay {
     columns: Vec<Column>,
     title: Option<Cow<'static, str>>,
     status: TestPassState,
+    /// Summary of the resources a program run used
+    usage: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -16,50 +21,94 @@ pub struct TestCaseDisplay {
 pub struct Column {
     title: Option<Cow<'static, str>>,
     content: String,
+    /// The content wasn't valid UTF-8 and is shown as a hex dump
+    hex: bool,
+}
+
+impl Column {
+    fn new(title: impl Into<Cow<'static, str>>, content: ByteString) -> Self {
+        let (content, hex) = content.display();
+        Column {
+            title: Some(title.into()),
+            content,
+            hex,
+        }
+    }
+}
+
+/// Columns for the arguments and files a program was given, if any
+fn program_input_columns(args: Vec<String>, files: Vec<InputFile>) -> Vec<Column> {
+    let args = (!args.is_empty()).then(|| Column {
+        title: Some(Cow::Borrowed("Arguments")),
+        content: args.join("\n"),
+        hex: false,
+    });
+    args.into_iter()
+        .chain(
+            files
+                .into_iter()
+                .map(|file| Column::new(file.name, file.content)),
+        )
+        .collect()
 }
 
 impl TestCaseDisplay {
     pub fn from_test_case(test_case: TestCase) -> Self {
+        let mut usage = None;
         let columns = match test_case.result_display {
             common::ResultDisplay::Empty => vec![],
             common::ResultDisplay::Text(e) => vec![Column {
                 title: None,
                 content: e,
+                hex: false,
             }],
             common::ResultDisplay::Diff { output, expected } => vec![
-                Column {
-                    title: Some(Cow::Borrowed("Output")),
-                    content: output,
-                },
-                Column {
-                    title: Some(Cow::Borrowed("Expected")),
-                    content: expected,
-                },
+                Column::new("Output", output),
+                Column::new("Expected", expected),
             ],
             common::ResultDisplay::Run {
                 input,
                 output,
                 error,
-            } => vec![
-                Column {
-                    title: Some(Cow::Borrowed("Input")),
-                    content: input.unwrap_or_default(),
-                },
-                Column {
-                    title: Some(Cow::Borrowed("Output")),
-                    content: output,
-                },
-                Column {
-                    title: Some(Cow::Borrowed("Error")),
-                    content: error,
-                },
-            ],
+                usage: run_usage,
+                args,
+                files,
+            } => {
+                usage = run_usage.as_ref().map(ResourceUsage::to_string);
+                let mut columns = program_input_columns(args, files);
+                columns.extend([
+                    Column::new("Input", input.unwrap_or_default()),
+                    Column::new("Output", output),
+                    Column::new("Error", error),
+                ]);
+                columns
+            }
+            common::ResultDisplay::Transcript {
+                entries,
+                error,
+                usage: run_usage,
+                args,
+                files,
+            } => {
+                usage = run_usage.as_ref().map(ResourceUsage::to_string);
+                let mut columns = program_input_columns(args, files);
+                columns.extend([
+                    Column {
+                        title: Some(Cow::Borrowed("Transcript")),
+                        content: common::format_transcript(&entries),
+                        hex: false,
+                    },
+                    Column::new("Error", error),
+                ]);
+                columns
+            }
         };
 
         TestCaseDisplay {
             columns,
             title: test_case.name.map(Cow::Owned),
             status: test_case.pass,
+            usage,
         }
     }
 }
@@ -70,7 +119,12 @@ pub struct OutputDisplay {
     tests: Vec<TestCaseDisplay>,
     passed: bool,
     timed_out: bool,
+    limit_exceeded: Option<&'static str>,
     judge_error: Option<String>,
+    /// The runner reused the result of an identical earlier run
+    cached: bool,
+    /// Summary of the resources the whole judge used
+    usage: String,
 }
 
 impl From<RunLangOutput> for OutputDisplay {
@@ -84,7 +138,14 @@ impl From<RunLangOutput> for OutputDisplay {
                 .collect(),
             passed: value.tests.pass,
             timed_out: value.timed_out,
+            // Time outs already have their own warning
+            limit_exceeded: value
+                .limit_exceeded
+                .filter(|&limit| limit != LimitExceeded::TimedOut)
+                .map(LimitExceeded::description),
             judge_error: (!value.stderr.is_empty()).then_some(value.stderr),
+            cached: value.cached,
+            usage: value.usage.to_string(),
         }
     }
 }
@@ -1,48 +1,382 @@
-use std::time::Duration;
+use std::{sync::LazyLock, time::Duration};
 
-use common::RunLangOutput;
+use common::{langs::langs, RunLangEvent, RunLangOutput, RunPriority, RunnerError, TestCase};
+use reqwest::{header::CONTENT_TYPE, Method};
 use serde::Serialize;
 
-use crate::error::Error;
+use crate::{
+    error::Error,
+    models::challenge::{ChallengeCategory, JudgeScore},
+    runners::{installed_versions, runners_for, Runner, RUNNERS},
+};
 
+/// How often the runners run the judge for a benchmark, from `YQ_BENCHMARK_RUNS`. Should match
+/// the runners' setting, it only bounds how long a benchmark may take.
+static BENCHMARK_RUNS: LazyLock<u32> = LazyLock::new(|| {
+    std::env::var("YQ_BENCHMARK_RUNS")
+        .ok()
+        .and_then(|runs| runs.parse().ok())
+        .unwrap_or(3)
+        .max(1)
+});
+
+/// Time a runner gets on top of the runs themselves, to queue the job, install the languages and
+/// start the sandboxes
+const RUNNER_OVERHEAD: Duration = Duration::from_secs(60);
+
+/// Runs `code` against a challenge judge
 #[derive(Serialize)]
-struct TestRunnerRequest<'a> {
-    lang: &'a str,
-    version: &'a str,
-    code: &'a str,
-    judge: &'a str,
+pub struct TestRunnerRequest<'a> {
+    pub lang: &'a str,
+    pub version: &'a str,
+    #[serde(with = "common::bytes::base64")]
+    pub code: &'a [u8],
+    pub judge: &'a str,
+    /// The language the judge is written in
+    pub judge_lang: &'a str,
+    /// In seconds
+    pub time_limit: i32,
+    pub priority: RunPriority,
+    /// Measure the runtime of the code, for challenges scored by runtime
+    pub benchmark: bool,
 }
 
-pub async fn test_solution(
-    code: &str,
-    language: &str,
-    version: &str,
-    judge: &str,
-) -> Result<RunLangOutput, Error> {
-    let client = reqwest::Client::new();
-    let resp = client
-        .post("http://localhost:3000")
-        .json(&TestRunnerRequest {
-            lang: language,
-            version,
-            code,
-            judge,
-        })
-        .timeout(Duration::from_secs(60))
+impl TestRunnerRequest<'_> {
+    /// How long the runner may take to answer, the time limit of every run plus some overhead
+    fn timeout(&self) -> Duration {
+        let multiplier = langs()
+            .get(self.lang)
+            .map_or(1.0, |lang| lang.time_limit_multiplier);
+        let runs = if self.benchmark { *BENCHMARK_RUNS } else { 1 };
+        Duration::from_secs(self.time_limit.max(0) as u64).mul_f32(multiplier) * runs
+            + RUNNER_OVERHEAD
+    }
+}
+
+/// Why a runner couldn't finish a job
+enum JobError {
+    /// Another runner might be able to do it, because this one couldn't be reached or
+    /// reported a retryable error
+    Retry(Error),
+    Fail(Error),
+}
+
+impl From<RunnerError> for JobError {
+    fn from(error: RunnerError) -> Self {
+        if error.retryable {
+            JobError::Retry(Error::Runner(error))
+        } else {
+            JobError::Fail(Error::Runner(error))
+        }
+    }
+}
+
+async fn send_runner_request(
+    runner: &Runner,
+    path: &str,
+    request: &TestRunnerRequest<'_>,
+) -> Result<reqwest::Response, JobError> {
+    let body = serde_json::to_vec(request).map_err(|_| JobError::Fail(Error::ServerError))?;
+    let resp = runner
+        .request(Method::POST, path, body)
+        .header(CONTENT_TYPE, "application/json")
+        .timeout(request.timeout())
         .send()
         .await
-        .map_err(|_e| Error::RunLang("Failed to connect to the lang runner".to_string()))?;
+        .map_err(|e| {
+            let error = Error::RunLang("Failed to connect to the lang runner".to_string());
+            if e.is_connect() {
+                runner.mark_unhealthy();
+                JobError::Retry(error)
+            } else {
+                JobError::Fail(error)
+            }
+        })?;
 
     if !resp.status().is_success() {
-        return Err(Error::RunLang(
-            resp.text().await.map_err(|_| Error::ServerError)?,
-        ));
+        let text = resp
+            .text()
+            .await
+            .map_err(|_| JobError::Fail(Error::ServerError))?;
+        return Err(match serde_json::from_str::<RunnerError>(&text) {
+            Ok(error) => error.into(),
+            Err(_) => JobError::Fail(Error::RunLang(text)),
+        });
     }
 
-    let out = resp
+    Ok(resp)
+}
+
+/// Gives a job to each runner in turn, in the order of `runners_for`, until one of them
+/// finishes it or fails in a way another runner wouldn't
+struct Attempts<'a> {
+    version: &'a str,
+    plugin_name: String,
+    runners: std::vec::IntoIter<&'static Runner>,
+    last_error: Option<Error>,
+}
+
+impl<'a> Attempts<'a> {
+    fn new(request: &'a TestRunnerRequest<'a>) -> Result<Self, Error> {
+        let plugin_name = langs()
+            .get(request.lang)
+            .map(|lang| lang.plugin_name.clone())
+            .ok_or_else(|| Error::RunLang(format!("Unknown language {}", request.lang)))?;
+        Ok(Attempts {
+            version: request.version,
+            runners: runners_for(&plugin_name, request.version).into_iter(),
+            plugin_name,
+            last_error: None,
+        })
+    }
+
+    /// The next runner to try, or the error of the last runner when none are left
+    fn next_runner(&mut self) -> Result<&'static Runner, Error> {
+        self.runners.next().ok_or_else(|| {
+            self.last_error
+                .take()
+                .expect("There is at least one runner")
+        })
+    }
+
+    /// The result of the job, or `None` if it should be given to the next runner
+    fn finish<T>(
+        &mut self,
+        runner: &Runner,
+        result: Result<T, JobError>,
+    ) -> Option<Result<T, Error>> {
+        match result {
+            Ok(result) => {
+                runner.mark_installed(&self.plugin_name, self.version);
+                Some(Ok(result))
+            }
+            Err(JobError::Retry(e)) => {
+                eprintln!(
+                    "Retrying on another lang runner, {} failed: {e:?}",
+                    runner.url
+                );
+                self.last_error = Some(e);
+                None
+            }
+            Err(JobError::Fail(e)) => Some(Err(e)),
+        }
+    }
+}
+
+async fn run_on(
+    runner: &Runner,
+    request: &TestRunnerRequest<'_>,
+) -> Result<RunLangOutput, JobError> {
+    send_runner_request(runner, "/", request)
+        .await?
         .json::<RunLangOutput>()
         .await
-        .map_err(|_| Error::RunLang("Failed to parse json".to_string()))?;
+        .map_err(|_| JobError::Fail(Error::RunLang("Failed to parse json".to_string())))
+}
+
+pub async fn test_solution(request: &TestRunnerRequest<'_>) -> Result<RunLangOutput, Error> {
+    let mut attempts = Attempts::new(request)?;
+    loop {
+        let runner = attempts.next_runner()?;
+        let _job = runner.start_job();
+        let result = run_on(runner, request).await;
+        if let Some(result) = attempts.finish(runner, result) {
+            return result;
+        }
+    }
+}
+
+/// The score of a passing solution that comes from the run instead of the code length, so
+/// either the score the judge reported or the runtime in microseconds. `None` for challenges
+/// scored by code length.
+pub fn runner_score(
+    category: ChallengeCategory,
+    judge_score: JudgeScore,
+    output: &RunLangOutput,
+) -> Option<Result<f64, Error>> {
+    if judge_score != JudgeScore::Unused {
+        Some(match output.tests.score {
+            None => Err(Error::RunLang(
+                "The judge did not report a score".to_string(),
+            )),
+            // Infinite scores couldn't be beaten or ranked
+            Some(score) if !score.is_finite() => Err(Error::RunLang(format!(
+                "The judge reported a score that isn't a finite number: {score}"
+            ))),
+            Some(score) => Ok(score),
+        })
+    } else if category.is_scored_by_runtime() {
+        Some(
+            output
+                .runtime_ms
+                .map(|runtime_ms| (runtime_ms * 1000.0).round())
+                .ok_or_else(|| {
+                    Error::RunLang("The lang runner did not report a runtime".to_string())
+                }),
+        )
+    } else {
+        None
+    }
+}
+
+/// The versions of a language that can be used to run a solution. The latest version
+/// always comes first, since the runners install it on demand.
+pub fn get_available_versions(language: &str) -> Result<Vec<String>, Error> {
+    let (plugin_name, mut versions) = langs()
+        .get(language)
+        .map(|lang| {
+            let allowed = std::iter::once(&lang.latest_version).chain(&lang.versions);
+            (
+                lang.plugin_name.clone(),
+                allowed.cloned().collect::<Vec<_>>(),
+            )
+        })
+        .ok_or(Error::NotFound)?;
 
-    Ok(out)
+    // Versions admins installed besides the allowed ones
+    for version in installed_versions(&plugin_name) {
+        if !versions.contains(&version) {
+            versions.push(version);
+        }
+    }
+    Ok(versions)
+}
+
+#[derive(Serialize)]
+struct InstallRequest<'a> {
+    lang: &'a str,
+    version: &'a str,
+}
+
+/// Installs a version of a language on every lang runner, even if the languages file doesn't
+/// allow it
+pub async fn install_runner_version(language: &str, version: &str) -> Result<(), Error> {
+    let plugin_name = langs()
+        .get(language)
+        .map(|lang| lang.plugin_name.clone())
+        .ok_or(Error::NotFound)?;
+    let body = serde_json::to_vec(&InstallRequest {
+        lang: language,
+        version,
+    })
+    .map_err(|_| Error::ServerError)?;
+
+    for runner in RUNNERS.iter() {
+        let resp = runner
+            .request(Method::POST, "/install", body.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send()
+            .await
+            .map_err(|_e| {
+                Error::RunLang(format!(
+                    "Failed to connect to the lang runner {}",
+                    runner.url
+                ))
+            })?;
+
+        if !resp.status().is_success() {
+            let text = resp.text().await.map_err(|_| Error::ServerError)?;
+            return Err(serde_json::from_str::<RunnerError>(&text)
+                .map_or(Error::RunLang(text), Error::Runner));
+        }
+        runner.mark_installed(&plugin_name, version);
+    }
+    Ok(())
+}
+
+/// Makes every lang runner read the languages file again
+pub async fn reload_runner_langs() -> Result<(), Error> {
+    for runner in RUNNERS.iter() {
+        let resp = runner
+            .request(Method::POST, "/reload-langs", vec![])
+            .send()
+            .await
+            .map_err(|_e| {
+                Error::RunLang(format!(
+                    "Failed to connect to the lang runner {}",
+                    runner.url
+                ))
+            })?;
+
+        if !resp.status().is_success() {
+            return Err(Error::RunLang(
+                resp.text().await.map_err(|_| Error::ServerError)?,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Progress of a streaming run
+pub enum RunProgress {
+    Queued { position: usize },
+    TestCase(TestCase),
+}
+
+/// A streaming run on one runner. It can only move to another runner before the first test
+/// case, which has already been shown.
+async fn stream_from(
+    runner: &Runner,
+    request: &TestRunnerRequest<'_>,
+    on_progress: &mut impl FnMut(RunProgress),
+) -> Result<RunLangOutput, JobError> {
+    let mut resp = send_runner_request(runner, "/stream", request).await?;
+
+    let mut started = false;
+    let retry_unless_started = |started: bool, error: Error| {
+        if started {
+            JobError::Fail(error)
+        } else {
+            JobError::Retry(error)
+        }
+    };
+    let mut line_buffer = vec![];
+    while let Some(chunk) = resp.chunk().await.map_err(|_| {
+        retry_unless_started(
+            started,
+            Error::RunLang("Lost connection to the lang runner".to_string()),
+        )
+    })? {
+        line_buffer.extend_from_slice(&chunk);
+
+        while let Some(i) = line_buffer.iter().position(|&d| d == b'\n') {
+            let line = line_buffer.drain(..=i).collect::<Vec<_>>();
+
+            match serde_json::from_slice::<RunLangEvent>(&line)
+                .map_err(|_| JobError::Fail(Error::RunLang("Failed to parse json".to_string())))?
+            {
+                RunLangEvent::Queued { position } => on_progress(RunProgress::Queued { position }),
+                RunLangEvent::TestCase(test_case) => {
+                    started = true;
+                    on_progress(RunProgress::TestCase(test_case))
+                }
+                RunLangEvent::Done(output) => return Ok(output),
+                RunLangEvent::Error(e) if e.retryable => {
+                    return Err(retry_unless_started(started, Error::Runner(e)))
+                }
+                RunLangEvent::Error(e) => return Err(JobError::Fail(Error::Runner(e))),
+            }
+        }
+    }
+
+    Err(JobError::Fail(Error::RunLang(
+        "The lang runner stopped without a result".to_string(),
+    )))
+}
+
+/// Like `test_solution`, but calls `on_progress` for every test case as soon as the
+/// judge outputs it.
+pub async fn test_solution_streaming(
+    request: &TestRunnerRequest<'_>,
+    mut on_progress: impl FnMut(RunProgress),
+) -> Result<RunLangOutput, Error> {
+    let mut attempts = Attempts::new(request)?;
+    loop {
+        let runner = attempts.next_runner()?;
+        let _job = runner.start_job();
+        let result = stream_from(runner, request, &mut on_progress).await;
+        if let Some(result) = attempts.finish(runner, result) {
+            return result;
+        }
+    }
 }
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE challenges
+    ADD COLUMN time_limit INTEGER NOT NULL DEFAULT 3;
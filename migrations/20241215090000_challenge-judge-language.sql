@@ -0,0 +1 @@
+ALTER TABLE challenges ADD COLUMN judge_lang VARCHAR(32) NOT NULL DEFAULT 'deno';
@@ -0,0 +1 @@
+ALTER TABLE solutions ALTER COLUMN code TYPE BYTEA USING convert_to(code, 'UTF8');
@@ -0,0 +1 @@
+ALTER TYPE challenge_category ADD VALUE 'fastest-code';
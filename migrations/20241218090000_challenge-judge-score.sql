@@ -0,0 +1,3 @@
+CREATE TYPE judge_score AS ENUM ('unused', 'lower-is-better', 'higher-is-better');
+
+ALTER TABLE challenges ADD COLUMN judge_score judge_score NOT NULL DEFAULT 'unused';
@@ -0,0 +1,4 @@
+-- Judges can report fractional scores
+ALTER TABLE solutions ALTER COLUMN score TYPE DOUBLE PRECISION;
+ALTER TABLE discord_messages ALTER COLUMN score TYPE DOUBLE PRECISION;
+ALTER TABLE discord_messages ALTER COLUMN previous_author_score TYPE DOUBLE PRECISION;
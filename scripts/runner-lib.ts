@@ -1,8 +1,35 @@
 export type PassState = "Pass" | "Fail" | "Warning" | "Info";
+/**
+ * Text, or base64 encoded bytes for output that isn't valid UTF-8
+ */
+export type ByteString = string | { base64: string };
 export type ResultDisplay =
-  | { Diff: { expected: string; output: string } }
+  | { Diff: { expected: ByteString; output: ByteString } }
   | { Text: string }
-  | { Run: { input?: string | undefined; output: string; error: string } };
+  | {
+      Run: {
+        input?: ByteString | undefined;
+        output: ByteString;
+        error: ByteString;
+        usage?: ResourceUsage | undefined;
+        args?: string[] | undefined;
+        files?: InputFile[] | undefined;
+      };
+    }
+  | {
+      Transcript: {
+        entries: TranscriptEntry[];
+        error: ByteString;
+        usage?: ResourceUsage | undefined;
+        args?: string[] | undefined;
+        files?: InputFile[] | undefined;
+      };
+    };
+export type InputFile = { name: string; content: ByteString };
+export type TranscriptEntry = {
+  source: "Judge" | "Program" | "Note";
+  text: ByteString;
+};
 export type Challenge = AsyncGenerator<TestCase, FinalVerdict, undefined>;
 
 export class TestCase {
@@ -35,22 +62,245 @@ export class TestCase {
 
 export class FinalVerdict {
   pass: boolean;
+  deterministic: boolean;
+  score: number | undefined;
 
-  constructor(pass: boolean) {
+  /**
+   * @param deterministic Whether the same code always gets the same result,
+   * which allows the runner to cache it
+   * @param score The score of the solution, for challenges scored by the judge
+   */
+  constructor(
+    pass: boolean,
+    deterministic: boolean = false,
+    score: number | undefined = undefined
+  ) {
     this.pass = pass;
+    this.deterministic = deterministic;
+    this.score = score;
   }
 }
 
+export type ResourceUsage = {
+  wallTimeMs: number;
+  /**
+   * User plus system time, if it could be measured. The Deno runner can't measure it for
+   * programs that run at the same time as others.
+   */
+  cpuTimeMs?: number | undefined;
+  /** Peak resident set size, if it could be measured */
+  peakMemoryKib?: number | undefined;
+};
+
 export type RunCodeResult = {
+  /** The output decoded as UTF-8, invalid bytes are replaced */
   stdout: string;
   stderr: string;
+  stdoutBytes: Uint8Array;
+  stderrBytes: Uint8Array;
   exitStatus: number;
+  usage: ResourceUsage;
 };
 
 export interface RunCompiledCodeResult extends RunCodeResult {
   compilationResult: RunCodeResult | undefined;
 }
 
+/**
+ * A running program that the judge talks to, implemented by the judge host
+ */
+export interface InteractiveProcess {
+  write(data: Uint8Array): Promise<void>;
+  /**
+   * Resolves with the next line of output including the newline, the rest of the
+   * output if it doesn't end with a newline, or undefined once the output is closed
+   */
+  readLine(): Promise<Uint8Array | undefined>;
+  closeStdin(): Promise<void>;
+  kill(): void;
+  /** Waits for the program to exit, the remaining output is what it wrote that wasn't read */
+  wait(): Promise<{
+    exitStatus: number;
+    remainingOutput: Uint8Array;
+    stderrBytes: Uint8Array;
+    usage: ResourceUsage;
+  }>;
+}
+
+export type StartInteractiveResult = {
+  /** Undefined when compilation failed */
+  process: InteractiveProcess | undefined;
+  compilationResult: RunCodeResult | undefined;
+};
+
+export type RunOptions = {
+  /** Command line arguments, passed where the language has `${ARGS}` */
+  args: string[];
+  /**
+   * Files to place in the working directory of the program, by file name. Every run has
+   * its own temporary working directory, which is removed after the run.
+   */
+  files: Record<string, string | Uint8Array>;
+};
+
+function inputDisplay(options: RunOptions): {
+  args: string[];
+  files: InputFile[];
+} {
+  return {
+    args: options.args,
+    files: Object.entries(options.files).map(([name, content]) => ({
+      name,
+      content: toByteString(content),
+    })),
+  };
+}
+
+export type InteractiveOptions = RunOptions & {
+  /** How long to wait for each reply of the program */
+  timeoutMs: number;
+  /** How long to wait for the program to exit after its input is closed */
+  exitTimeoutMs: number;
+};
+
+export type InteractiveResult = {
+  exitStatus: number;
+  stderr: string;
+  usage: ResourceUsage | undefined;
+  /** The program didn't reply in time and was killed */
+  timedOut: boolean;
+};
+
+/**
+ * A session where the judge and the program take turns. Every message is recorded,
+ * and the transcript is shown once the session is finished.
+ */
+export class InteractiveSession {
+  private process: InteractiveProcess | undefined;
+  private options: InteractiveOptions;
+  private entries: TranscriptEntry[] = [];
+  private onFinish: (testCase: TestCase) => void;
+  /** Set once the program didn't reply in time, after which it's killed */
+  public timedOut: boolean = false;
+
+  constructor(
+    process: InteractiveProcess | undefined,
+    options: InteractiveOptions,
+    onFinish: (testCase: TestCase) => void
+  ) {
+    this.process = process;
+    this.options = options;
+    this.onFinish = onFinish;
+  }
+
+  private note(text: string) {
+    this.entries.push({ source: "Note", text });
+  }
+
+  async write(data: string | Uint8Array): Promise<void> {
+    if (this.process === undefined || this.timedOut) {
+      return;
+    }
+    const bytes =
+      typeof data === "string" ? new TextEncoder().encode(data) : data;
+    this.entries.push({ source: "Judge", text: toByteString(bytes) });
+    try {
+      await this.process.write(bytes);
+    } catch {
+      this.note("The program closed its input");
+    }
+  }
+
+  async writeLine(line: string): Promise<void> {
+    await this.write(line + "\n");
+  }
+
+  /**
+   * Reads the next line of output, without the line ending. Resolves with undefined
+   * if the program closed its output or didn't reply in time.
+   */
+  async readLine(
+    timeoutMs: number = this.options.timeoutMs
+  ): Promise<string | undefined> {
+    const line = await this.readLineBytes(timeoutMs);
+    return line === undefined
+      ? undefined
+      : new TextDecoder().decode(line).replace(/\r?\n$/u, "");
+  }
+
+  async readLineBytes(
+    timeoutMs: number = this.options.timeoutMs
+  ): Promise<Uint8Array | undefined> {
+    if (this.process === undefined || this.timedOut) {
+      return undefined;
+    }
+    let timer: ReturnType<typeof setTimeout> | undefined;
+    const timeout = new Promise<"timeout">((resolve) => {
+      timer = setTimeout(() => resolve("timeout"), timeoutMs);
+    });
+    const line = await Promise.race([this.process.readLine(), timeout]);
+    clearTimeout(timer);
+    if (line === "timeout") {
+      this.timedOut = true;
+      this.note(`No reply within ${timeoutMs} ms`);
+      this.process.kill();
+      return undefined;
+    }
+    if (line === undefined) {
+      this.note("The program closed its output");
+      return undefined;
+    }
+    this.entries.push({ source: "Program", text: toByteString(line) });
+    return line;
+  }
+
+  /**
+   * Closes the input of the program, waits for it to exit and shows the transcript
+   */
+  async finish(): Promise<InteractiveResult> {
+    const process = this.process;
+    if (process === undefined) {
+      return { exitStatus: 1, stderr: "", usage: undefined, timedOut: false };
+    }
+    this.process = undefined;
+
+    await process.closeStdin();
+    const timer = setTimeout(() => {
+      this.note(`Didn't exit within ${this.options.exitTimeoutMs} ms`);
+      process.kill();
+    }, this.options.exitTimeoutMs);
+    const { exitStatus, remainingOutput, stderrBytes, usage } =
+      await process.wait();
+    clearTimeout(timer);
+
+    if (remainingOutput.length > 0) {
+      this.entries.push({
+        source: "Program",
+        text: toByteString(remainingOutput),
+      });
+    }
+    if (exitStatus !== 0) {
+      this.note(`Exited with status ${exitStatus}`);
+    }
+    this.onFinish(
+      new TestCase(undefined, this.timedOut ? "Warning" : "Info", {
+        Transcript: {
+          entries: this.entries,
+          error: toByteString(stderrBytes),
+          usage,
+          ...inputDisplay(this.options),
+        },
+      })
+    );
+    return {
+      exitStatus,
+      stderr: new TextDecoder().decode(stderrBytes),
+      usage,
+      timedOut: this.timedOut,
+    };
+  }
+}
+
 export class StringResult {
   protected context: Context;
   public text: string;
@@ -81,10 +331,13 @@ export class StringResult {
 
 export class RunResult extends StringResult {
   private stderr: string;
+  /** Resources used by the program, not including compilation */
+  public usage: ResourceUsage;
 
   public constructor(context: Context, result: RunCodeResult) {
     super(context, result.stdout);
     this.stderr = result.stderr;
+    this.usage = result.usage;
   }
 
   public error() {
@@ -131,24 +384,43 @@ export type FilterCasesOptions = {
 };
 
 export class Context {
+  /** The code decoded as UTF-8, invalid bytes are replaced */
   public code: string;
+  public codeBytes: Uint8Array;
   private onRunCallback: (
-    code: string,
-    input: string | undefined
+    code: string | Uint8Array,
+    input: string | Uint8Array | undefined,
+    options: RunOptions
   ) => Promise<RunCompiledCodeResult>;
+  private onInteractCallback: (
+    code: string | Uint8Array,
+    options: RunOptions
+  ) => Promise<StartInteractiveResult>;
   public testCases: TestCase[];
+  /**
+   * Set this when the judge gives the same result every time for the same code,
+   * so the result can be cached. Test cases must not be shuffled in that case.
+   */
+  public deterministic: boolean = false;
 
   private runs: number = 0;
 
   constructor(
-    code: string,
+    codeBytes: Uint8Array,
     onRunCallback: (
-      code: string,
-      input: string | undefined
-    ) => Promise<RunCompiledCodeResult>
+      code: string | Uint8Array,
+      input: string | Uint8Array | undefined,
+      options: RunOptions
+    ) => Promise<RunCompiledCodeResult>,
+    onInteractCallback: (
+      code: string | Uint8Array,
+      options: RunOptions
+    ) => Promise<StartInteractiveResult>
   ) {
-    this.code = code;
+    this.code = new TextDecoder().decode(codeBytes);
+    this.codeBytes = codeBytes;
     this.onRunCallback = onRunCallback;
+    this.onInteractCallback = onInteractCallback;
     this.testCases = [];
   }
 
@@ -172,11 +444,15 @@ export class Context {
 
     const hands = shuffleAndDeal(testCases, options);
 
-    // TODO: When running code becomes thread safe, this should run in paralel
-    for (const hand of hands) {
-      yield (
-        await this.run(hand.map((i) => i[0]).join(options.inputSeperator))
-      ).assertEquals(hand.map((i) => i[1]).join(options.outputSeperator));
+    const results = await Promise.all(
+      hands.map((hand) =>
+        this.run(hand.map((i) => i[0]).join(options.inputSeperator))
+      )
+    );
+    for (const [index, hand] of hands.entries()) {
+      yield results[index].assertEquals(
+        hand.map((i) => i[1]).join(options.outputSeperator)
+      );
     }
   }
 
@@ -192,11 +468,13 @@ export class Context {
     };
     const hands = shuffleAndDeal(testCases, options);
 
-    // TODO: When running code becomes thread safe, this should run in paralel
-    for (const hand of hands) {
-      yield (
-        await this.run(hand.map((i) => i[0]).join(options.inputSeperator))
-      ).assertEquals(
+    const results = await Promise.all(
+      hands.map((hand) =>
+        this.run(hand.map((i) => i[0]).join(options.inputSeperator))
+      )
+    );
+    for (const [index, hand] of hands.entries()) {
+      yield results[index].assertEquals(
         hand
           .filter((i) => i[1])
           .map((i) => i[0])
@@ -205,27 +483,46 @@ export class Context {
     }
   }
 
-  async run(input?: string | undefined): Promise<RunResult> {
-    return this.runCode(this.code, input);
+  /**
+   * Runs the code. Every run has its own working directory, so several runs can be
+   * awaited at once with `Promise.all`.
+   * @param input Written to the standard input of the program
+   * @param overrideOptions Command line arguments and input files (optional)
+   */
+  async run(
+    input?: string | Uint8Array | undefined,
+    overrideOptions: Partial<RunOptions> = {}
+  ): Promise<RunResult> {
+    return this.runCode(this.codeBytes, input, overrideOptions);
   }
 
-  async runCode(code: string, input?: string | undefined) {
-    const result = await this.onRunCallback(code, input);
-    let { stderr, stdout, compilationResult } = result;
+  async runCode(
+    code: string | Uint8Array,
+    input?: string | Uint8Array | undefined,
+    overrideOptions: Partial<RunOptions> = {}
+  ) {
+    const options: RunOptions = { args: [], files: {}, ...overrideOptions };
+    // Numbered when started, runs can finish in any order
+    const name = `Run #${++this.runs}`;
+    const result = await this.onRunCallback(code, input, options);
+    let { stderrBytes, stdoutBytes, usage, compilationResult } = result;
     const compilationFailed =
       compilationResult && compilationResult.exitStatus !== 0;
     if (compilationFailed) {
-      stdout = compilationResult.stdout;
-      stderr = compilationResult.stderr;
+      stdoutBytes = compilationResult.stdoutBytes;
+      stderrBytes = compilationResult.stderrBytes;
+      usage = compilationResult.usage;
     }
     const runDisplay: TestCase = new TestCase(
-      `Run #${++this.runs}`,
+      name,
       compilationFailed ? "Warning" : "Info",
       {
         Run: {
-          input: input,
-          output: stdout,
-          error: stderr,
+          input: input === undefined ? undefined : toByteString(input),
+          output: toByteString(stdoutBytes),
+          error: toByteString(stderrBytes),
+          usage,
+          ...inputDisplay(options),
         },
       }
     );
@@ -233,20 +530,91 @@ export class Context {
     return new RunResult(this, result);
   }
 
+  /**
+   * Starts the code for an interactive session, where the judge writes to the
+   * program and reads its replies in turns. Call `finish` on the session when done.
+   * @param overrideOptions Special options (optional)
+   */
+  async interact(
+    overrideOptions: Partial<InteractiveOptions> = {}
+  ): Promise<InteractiveSession> {
+    return this.interactCode(this.codeBytes, overrideOptions);
+  }
+
+  async interactCode(
+    code: string | Uint8Array,
+    overrideOptions: Partial<InteractiveOptions> = {}
+  ): Promise<InteractiveSession> {
+    const options: InteractiveOptions = {
+      timeoutMs: 1000,
+      exitTimeoutMs: 1000,
+      args: [],
+      files: {},
+      ...overrideOptions,
+    };
+    const name = `Run #${++this.runs}`;
+    const { process, compilationResult } = await this.onInteractCallback(
+      code,
+      options
+    );
+    if (compilationResult && compilationResult.exitStatus !== 0) {
+      const compileDisplay = new TestCase(name, "Warning", {
+        Run: {
+          output: toByteString(compilationResult.stdoutBytes),
+          error: toByteString(compilationResult.stderrBytes),
+          usage: compilationResult.usage,
+        },
+      });
+      console.log(JSON.stringify(compileDisplay));
+    }
+    return new InteractiveSession(process, options, (testCase) =>
+      console.log(JSON.stringify(testCase.setName(name)))
+    );
+  }
+
   registerTestCase(testCase: TestCase): TestCase {
     this.testCases.push(testCase);
     return testCase;
   }
 
-  noFailures(): FinalVerdict {
+  /**
+   * @param score The score of the solution, for challenges scored by the judge
+   */
+  noFailures(score?: number | undefined): FinalVerdict {
     if (this.testCases.every((i) => i.pass !== "Fail")) {
-      return new FinalVerdict(true);
+      return new FinalVerdict(true, this.deterministic, score);
     } else {
-      return new FinalVerdict(false);
+      return new FinalVerdict(false, this.deterministic, score);
     }
   }
 }
 
+export function encodeBase64(bytes: Uint8Array): string {
+  let binary = "";
+  for (const byte of bytes) {
+    binary += String.fromCharCode(byte);
+  }
+  return btoa(binary);
+}
+
+export function decodeBase64(text: string): Uint8Array {
+  return Uint8Array.from(atob(text), (c) => c.charCodeAt(0));
+}
+
+/**
+ * Keeps text as is, and base64 encodes bytes that aren't valid UTF-8
+ */
+export function toByteString(value: string | Uint8Array): ByteString {
+  if (typeof value === "string") {
+    return value;
+  }
+  try {
+    return new TextDecoder("utf-8", { fatal: true }).decode(value);
+  } catch {
+    return { base64: encodeBase64(value) };
+  }
+}
+
 export const eqIgnoreTrailingWhitespace = (a: string, b: string): boolean => {
   const [a_stripped, b_stripped] = [a, b].map((text) =>
     text.replace(/\s*(?=\n|$)/gu, "")
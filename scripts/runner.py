@@ -0,0 +1,666 @@
+"""Judge host for judges written in Python.
+
+Speaks the same protocol as runner.ts: reads `{code, lang, judge}` as JSON from the first
+line of stdin, with the code base64 encoded, and prints one JSON `TestCase` per line, followed
+by the `FinalVerdict`. To run programs concurrently it asks the lang runner for slots by
+printing `"AcquireSlot"`, which is answered with `true` or `false` on stdin, and gives them
+back with `"ReleaseSlot"`.
+
+The judge is Python source that defines a generator function called `judge`, which takes a
+`Context`, yields test cases and returns a `FinalVerdict`:
+
+    def judge(context):
+        yield context.run("input").assert_equals("expected output")
+        yield from context.run_test_cases([("Input", "Expected Output")])
+        return context.no_failures()
+
+Interactive challenges talk to the program in turns:
+
+    def judge(context):
+        session = context.interact(timeout_ms=1000)
+        session.write_line("5")
+        if session.read_line() != "25":
+            yield context.register_test_case(TestCase(None, "Fail", {"Text": "Wrong"}))
+        session.finish()
+        return context.no_failures()
+"""
+
+import base64
+import concurrent.futures
+import json
+import queue
+import random
+import re
+import os
+import shutil
+import subprocess
+import sys
+import tempfile
+import threading
+import time
+
+
+class TestCase:
+    def __init__(self, name, pass_state, result_display):
+        self.name = name
+        self.pass_state = pass_state
+        self.result_display = result_display
+
+    def set_name(self, name):
+        self.name = name
+        return self
+
+    def replace_fail_state(self, state):
+        if self.pass_state == "Fail":
+            self.pass_state = state
+        return self
+
+    def to_json(self):
+        return {
+            "name": self.name,
+            "pass": self.pass_state,
+            "resultDisplay": self.result_display,
+        }
+
+
+class FinalVerdict:
+    def __init__(self, passed, deterministic=False, score=None):
+        """`deterministic` means the same code always gets the same result, which allows the
+        runner to cache it. `score` is the score of the solution for challenges scored by the
+        judge."""
+        self.passed = passed
+        self.deterministic = deterministic
+        self.score = score
+
+    def to_json(self):
+        return {
+            "pass": self.passed,
+            "deterministic": self.deterministic,
+            "score": self.score,
+        }
+
+
+def to_byte_string(value):
+    """Keeps text as is, and base64 encodes bytes that aren't valid UTF-8"""
+    if isinstance(value, str):
+        return value
+    try:
+        return value.decode("utf-8")
+    except UnicodeDecodeError:
+        return {"base64": base64.b64encode(value).decode("ascii")}
+
+
+def to_bytes(value):
+    return value.encode("utf-8") if isinstance(value, str) else value
+
+
+def eq_ignore_trailing_whitespace(a, b):
+    def strip(text):
+        return re.sub(r"\s*(?=\n|$)", "", text)
+
+    return strip(a) == strip(b)
+
+
+class StringResult:
+    def __init__(self, context, text):
+        self.context = context
+        self.text = text
+
+    def assert_equals(self, value):
+        valid = eq_ignore_trailing_whitespace(self.text, value)
+        test_case = TestCase(
+            None,
+            "Pass" if valid else "Fail",
+            {"Diff": {"expected": value, "output": self.text}},
+        )
+        self.context.test_cases.append(test_case)
+        return test_case
+
+    def check(self, callback):
+        test_case = callback(self.text)
+        self.context.test_cases.append(test_case)
+        return test_case
+
+
+class RunResult(StringResult):
+    """`text` and `stderr` are decoded as UTF-8 with invalid bytes replaced, `stdout_bytes`
+    and `stderr_bytes` are the raw output. `usage` has the resources the program used, with
+    the same keys as in the `Run` result display."""
+
+    def __init__(self, context, result):
+        super().__init__(context, result.stdout.decode("utf-8", "replace"))
+        self.stderr = result.stderr.decode("utf-8", "replace")
+        self.stdout_bytes = result.stdout
+        self.stderr_bytes = result.stderr
+        self.usage = result.usage
+
+
+class ProcessResult:
+    def __init__(self, stdout, stderr, returncode, usage):
+        self.stdout = stdout
+        self.stderr = stderr
+        self.returncode = returncode
+        self.usage = usage
+
+
+# Where the language of the code is, the sandbox usually mounts it at /lang
+LANG_LOCATION = os.environ.get("YQ_LANG_LOCATION", "/lang")
+TMP_DIR = tempfile.gettempdir()
+
+_output_lock = threading.Lock()
+
+
+def emit(value):
+    """Prints a line of JSON, runs on other threads print too"""
+    with _output_lock:
+        print(json.dumps(value), flush=True)
+
+
+class Slots:
+    """Programs run concurrently with extra slots from the lang runner, so they count against
+    its limit on concurrent runs. The judge's own slot runs one program at a time, programs
+    wait for it when the lang runner has no free slots."""
+
+    def __init__(self):
+        self._own_slot = threading.Semaphore(1)
+        self._request_lock = threading.Lock()
+
+    def acquire(self):
+        """Returns a function that gives the slot back"""
+        if self._own_slot.acquire(blocking=False):
+            return self._own_slot.release
+        with self._request_lock:
+            emit("AcquireSlot")
+            granted = json.loads(sys.stdin.readline() or "false") is True
+        if granted:
+            return lambda: emit("ReleaseSlot")
+        self._own_slot.acquire()
+        return self._own_slot.release
+
+
+SLOTS = Slots()
+
+
+def run_process(args, input, env, cwd):
+    """Like `subprocess.run`, but waits for the process with `os.wait4` to get its resource
+    usage"""
+    start_time = time.perf_counter()
+    process = subprocess.Popen(
+        args,
+        stdin=subprocess.PIPE,
+        stdout=subprocess.PIPE,
+        stderr=subprocess.PIPE,
+        env=env,
+        cwd=cwd,
+    )
+    outputs = {}
+
+    def read(name, stream):
+        outputs[name] = stream.read()
+
+    readers = [
+        threading.Thread(target=read, args=("stdout", process.stdout)),
+        threading.Thread(target=read, args=("stderr", process.stderr)),
+    ]
+    for reader in readers:
+        reader.start()
+    try:
+        process.stdin.write(input)
+        process.stdin.close()
+    except BrokenPipeError:
+        # The program doesn't have to read all of its input
+        pass
+    for reader in readers:
+        reader.join()
+
+    _, status, rusage = os.wait4(process.pid, 0)
+    process.returncode = os.waitstatus_to_exitcode(status)
+    return ProcessResult(
+        outputs["stdout"],
+        outputs["stderr"],
+        process.returncode,
+        {
+            "wallTimeMs": (time.perf_counter() - start_time) * 1000,
+            "cpuTimeMs": (rusage.ru_utime + rusage.ru_stime) * 1000,
+            # ru_maxrss is in KiB on Linux
+            "peakMemoryKib": rusage.ru_maxrss,
+        },
+    )
+
+
+class InteractiveSession:
+    """A session where the judge and the program take turns. Every message is recorded, and
+    the transcript is shown once the session is finished. `timed_out` is set once the
+    program didn't reply in time, after which it's killed."""
+
+    def __init__(self, process, timeout_ms, exit_timeout_ms, input_display, on_finish):
+        self.timed_out = False
+        self._input_display = input_display
+        self._process = process
+        self._timeout_ms = timeout_ms
+        self._exit_timeout_ms = exit_timeout_ms
+        self._on_finish = on_finish
+        self._entries = []
+        self._start_time = time.perf_counter()
+        if process is None:
+            return
+
+        # Lines are read in the background, so reading can be given up on after a timeout
+        self._lines = queue.Queue()
+        self._stderr = {}
+
+        def read_lines():
+            for line in iter(process.stdout.readline, b""):
+                self._lines.put(line)
+            self._lines.put(None)
+
+        def read_stderr():
+            self._stderr["stderr"] = process.stderr.read()
+
+        self._readers = [
+            threading.Thread(target=read_lines, daemon=True),
+            threading.Thread(target=read_stderr, daemon=True),
+        ]
+        for reader in self._readers:
+            reader.start()
+
+    def _note(self, text):
+        self._entries.append({"source": "Note", "text": text})
+
+    def write(self, data):
+        """`data` can be either `str` or `bytes`"""
+        if self._process is None or self.timed_out:
+            return
+        data = to_bytes(data)
+        self._entries.append({"source": "Judge", "text": to_byte_string(data)})
+        try:
+            self._process.stdin.write(data)
+            self._process.stdin.flush()
+        except BrokenPipeError:
+            self._note("The program closed its input")
+
+    def write_line(self, line):
+        self.write(line + "\n")
+
+    def read_line(self, timeout_ms=None):
+        """Reads the next line of output without the line ending. Returns `None` if the
+        program closed its output or didn't reply in time."""
+        line = self.read_line_bytes(timeout_ms)
+        if line is None:
+            return None
+        return re.sub(r"\r?\n$", "", line.decode("utf-8", "replace"))
+
+    def read_line_bytes(self, timeout_ms=None):
+        if self._process is None or self.timed_out:
+            return None
+        timeout_ms = self._timeout_ms if timeout_ms is None else timeout_ms
+        try:
+            line = self._lines.get(timeout=timeout_ms / 1000)
+        except queue.Empty:
+            self.timed_out = True
+            self._note(f"No reply within {timeout_ms} ms")
+            self._process.kill()
+            return None
+        if line is None:
+            # Keep the end marker for later reads
+            self._lines.put(None)
+            self._note("The program closed its output")
+            return None
+        self._entries.append({"source": "Program", "text": to_byte_string(line)})
+        return line
+
+    def finish(self):
+        """Closes the input of the program, waits for it to exit and shows the transcript.
+        Returns a `ProcessResult` with the remaining output as `stdout`."""
+        process = self._process
+        if process is None:
+            return ProcessResult(b"", b"", 1, None)
+        self._process = None
+
+        try:
+            process.stdin.close()
+        except BrokenPipeError:
+            pass
+        timer = threading.Timer(self._exit_timeout_ms / 1000, process.kill)
+        timer.start()
+        _, status, rusage = os.wait4(process.pid, 0)
+        exited_in_time = timer.is_alive()
+        timer.cancel()
+        process.returncode = os.waitstatus_to_exitcode(status)
+        for reader in self._readers:
+            reader.join()
+        usage = {
+            "wallTimeMs": (time.perf_counter() - self._start_time) * 1000,
+            "cpuTimeMs": (rusage.ru_utime + rusage.ru_stime) * 1000,
+            "peakMemoryKib": rusage.ru_maxrss,
+        }
+
+        remaining = b""
+        while (line := self._lines.get()) is not None:
+            remaining += line
+        if remaining:
+            self._entries.append(
+                {"source": "Program", "text": to_byte_string(remaining)}
+            )
+        if not exited_in_time:
+            self._note(f"Didn't exit within {self._exit_timeout_ms} ms")
+        if process.returncode != 0:
+            self._note(f"Exited with status {process.returncode}")
+        stderr = self._stderr.get("stderr", b"")
+        self._on_finish(
+            TestCase(
+                None,
+                "Warning" if self.timed_out else "Info",
+                {
+                    "Transcript": {
+                        "entries": self._entries,
+                        "error": to_byte_string(stderr),
+                        "usage": usage,
+                        **self._input_display,
+                    }
+                },
+            )
+        )
+        return ProcessResult(remaining, stderr, process.returncode, usage)
+
+
+def input_display(args, files):
+    return {
+        "args": args,
+        "files": [
+            {"name": name, "content": to_byte_string(content)}
+            for name, content in files.items()
+        ],
+    }
+
+
+def shuffle_and_deal(test_cases, shuffle, number_of_runs):
+    test_cases = list(test_cases)
+    if shuffle:
+        random.shuffle(test_cases)
+    return [test_cases[i::number_of_runs] for i in range(number_of_runs)]
+
+
+class Context:
+    def __init__(self, code_bytes, lang):
+        # The code decoded as UTF-8 with invalid bytes replaced
+        self.code = code_bytes.decode("utf-8", "replace")
+        self.code_bytes = code_bytes
+        self.lang = lang
+        self.test_cases = []
+        # Set this when the judge gives the same result every time for the same code,
+        # so the result can be cached. Test cases must not be shuffled in that case.
+        self.deterministic = False
+        self._lock = threading.Lock()
+        self._runs = 0
+        self._run_directories = 0
+        # Compilations by code as futures, shared by runs of the same code
+        self._compiled = {}
+        self._compilations = 0
+
+    def _next_run_name(self):
+        # Numbered when started, runs can finish in any order
+        with self._lock:
+            self._runs += 1
+            return f"Run #{self._runs}"
+
+    def run(self, input=None, args=None, files=None):
+        return self.run_code(self.code_bytes, input, args, files)
+
+    def run_code(self, code, input=None, args=None, files=None):
+        """`code` and `input` can be either `str` or `bytes`. `args` are command line
+        arguments, passed where the language has `${ARGS}`. `files` maps file names to
+        contents, which are placed in the working directory of the program. Every run has its
+        own temporary working directory, so runs can happen on several threads at once."""
+        args = args or []
+        files = files or {}
+        code = to_bytes(code)
+        name = self._next_run_name()
+
+        release_slot = SLOTS.acquire()
+        try:
+            directory = self._create_run_directory(code, files)
+            try:
+                result, compilation_failed = self._compile_and_run(
+                    code, directory, to_bytes(input or ""), args
+                )
+            finally:
+                shutil.rmtree(directory, ignore_errors=True)
+        finally:
+            release_slot()
+
+        run_display = TestCase(
+            name,
+            "Warning" if compilation_failed else "Info",
+            {
+                "Run": {
+                    "input": None if input is None else to_byte_string(input),
+                    "output": to_byte_string(result.stdout),
+                    "error": to_byte_string(result.stderr),
+                    "usage": result.usage,
+                    **input_display(args, files),
+                }
+            },
+        )
+        emit(run_display.to_json())
+        return RunResult(self, result)
+
+    def interact(self, timeout_ms=1000, exit_timeout_ms=1000, args=None, files=None):
+        """Starts the code for an interactive session, where the judge writes to the program
+        and reads its replies in turns. `timeout_ms` is how long to wait for each reply.
+        `args` and `files` work like in `run_code`. Call `finish` on the session when done."""
+        return self.interact_code(
+            self.code_bytes, timeout_ms, exit_timeout_ms, args, files
+        )
+
+    def interact_code(
+        self, code, timeout_ms=1000, exit_timeout_ms=1000, args=None, files=None
+    ):
+        args = args or []
+        files = files or {}
+        code = to_bytes(code)
+        name = self._next_run_name()
+
+        # The slot and working directory are held until the session is finished
+        release_slot = SLOTS.acquire()
+        directory = None
+
+        def clean_up():
+            if directory is not None:
+                shutil.rmtree(directory, ignore_errors=True)
+            release_slot()
+
+        try:
+            directory = self._create_run_directory(code, files)
+            compilation_result, output_location = self._compile(code, directory)
+            process = None
+            if compilation_result is not None and compilation_result.returncode != 0:
+                clean_up()
+                compile_display = TestCase(
+                    name,
+                    "Warning",
+                    {
+                        "Run": {
+                            "output": to_byte_string(compilation_result.stdout),
+                            "error": to_byte_string(compilation_result.stderr),
+                            "usage": compilation_result.usage,
+                        }
+                    },
+                )
+                emit(compile_display.to_json())
+            else:
+                command, env = self._command(
+                    self.lang["runCommand"], directory, output_location, args
+                )
+                process = subprocess.Popen(
+                    command,
+                    stdin=subprocess.PIPE,
+                    stdout=subprocess.PIPE,
+                    stderr=subprocess.PIPE,
+                    env=env,
+                    cwd=directory,
+                )
+        except BaseException:
+            clean_up()
+            raise
+
+        def on_finish(test_case):
+            clean_up()
+            emit(test_case.set_name(name).to_json())
+
+        return InteractiveSession(
+            process,
+            timeout_ms,
+            exit_timeout_ms,
+            input_display(args, files),
+            on_finish,
+        )
+
+    def _create_run_directory(self, code, files):
+        for name in files:
+            if not re.fullmatch(r"\w[\w.-]*", name) or name in ("code", "output"):
+                raise ValueError(f"Invalid input file name {name!r}")
+        with self._lock:
+            directory = f"{TMP_DIR}/run{self._run_directories}"
+            self._run_directories += 1
+        os.mkdir(directory)
+        with open(f"{directory}/code", "wb") as file:
+            file.write(code)
+        for name, content in files.items():
+            with open(f"{directory}/{name}", "wb") as file:
+                file.write(to_bytes(content))
+        return directory
+
+    def _compile_and_run(self, code, directory, input, args):
+        compilation_result, output_location = self._compile(code, directory)
+        if compilation_result is not None and compilation_result.returncode != 0:
+            return compilation_result, True
+        command, env = self._command(
+            self.lang["runCommand"], directory, output_location, args
+        )
+        return run_process(command, input, env, directory), False
+
+    def _compile(self, code, directory):
+        """Compiles the code if the language needs it and it wasn't compiled before. Returns
+        the compilation result, if it was compiled, and the output location."""
+        if not self.lang["compileCommand"]:
+            return None, f"{directory}/output"
+
+        with self._lock:
+            compilation = self._compiled.get(code)
+            compiling = compilation is None
+            if compiling:
+                compilation = concurrent.futures.Future()
+                self._compiled[code] = compilation
+                output_location = f"{TMP_DIR}/executable{self._compilations}"
+                self._compilations += 1
+
+        if compiling:
+            try:
+                args, env = self._command(
+                    self.lang["compileCommand"], directory, output_location
+                )
+                result = run_process(args, b"", env, directory)
+            except BaseException as e:
+                with self._lock:
+                    del self._compiled[code]
+                compilation.set_exception(e)
+                raise
+            if result.returncode != 0:
+                # Show the compiler output again on the next run
+                with self._lock:
+                    del self._compiled[code]
+            compilation.set_result((result, output_location))
+
+        return compilation.result()
+
+    def _command(self, command, directory, output_location, args=()):
+        def replace_tokens(text):
+            return (
+                text.replace("${LANG_LOCATION}", LANG_LOCATION)
+                .replace("${FILE_LOCATION}", f"{directory}/code")
+                .replace("${OUTPUT_LOCATION}", output_location)
+            )
+
+        # `${ARGS}` is a whole argument, when the command doesn't have it the arguments go last
+        if "${ARGS}" not in command:
+            command = [*command, "${ARGS}"]
+        command = [
+            value
+            for arg in command
+            for value in (args if arg == "${ARGS}" else [replace_tokens(arg)])
+        ]
+        return (
+            command,
+            {key: replace_tokens(value) for key, value in self.lang["env"]},
+        )
+
+    def _run_concurrently(self, inputs):
+        with concurrent.futures.ThreadPoolExecutor(max(len(inputs), 1)) as executor:
+            return list(executor.map(self.run, inputs))
+
+    def run_test_cases(
+        self,
+        test_cases,
+        input_separator="\n",
+        output_separator="\n",
+        number_of_runs=2,
+        shuffle=True,
+    ):
+        """Runs the code with each input and checks it against the matching output. Shuffles
+        the test cases and divides them over multiple runs, which run concurrently."""
+        hands = shuffle_and_deal(test_cases, shuffle, number_of_runs)
+        results = self._run_concurrently(
+            [input_separator.join(i[0] for i in hand) for hand in hands]
+        )
+        for hand, result in zip(hands, results):
+            yield result.assert_equals(output_separator.join(i[1] for i in hand))
+
+    def run_filter_cases(
+        self, test_cases, input_separator="\n", number_of_runs=2, shuffle=True
+    ):
+        """For challenges where the goal is to output all inputs that match some condition"""
+        hands = shuffle_and_deal(test_cases, shuffle, number_of_runs)
+        results = self._run_concurrently(
+            [input_separator.join(i[0] for i in hand) for hand in hands]
+        )
+        for hand, result in zip(hands, results):
+            yield result.assert_equals(input_separator.join(i[0] for i in hand if i[1]))
+
+    def register_test_case(self, test_case):
+        self.test_cases.append(test_case)
+        return test_case
+
+    def no_failures(self, score=None):
+        return FinalVerdict(
+            all(i.pass_state != "Fail" for i in self.test_cases),
+            self.deterministic,
+            score,
+        )
+
+
+def main():
+    # Only the first line, the rest of stdin answers requests to the lang runner
+    data = json.loads(sys.stdin.readline())
+
+    namespace = {
+        "Context": Context,
+        "TestCase": TestCase,
+        "FinalVerdict": FinalVerdict,
+        "eq_ignore_trailing_whitespace": eq_ignore_trailing_whitespace,
+    }
+    exec(data["judge"], namespace)
+    generator = namespace["judge"](
+        Context(base64.b64decode(data["code"]), data["lang"])
+    )
+
+    while True:
+        try:
+            test_case = next(generator)
+        except StopIteration as verdict:
+            emit(verdict.value.to_json())
+            return
+        emit(test_case.to_json())
+
+
+main()
@@ -1,8 +1,8 @@
 import { argv, stdin } from 'node:process';
-import { writeFile } from 'node:fs/promises';
+import { mkdir, rm, writeFile } from 'node:fs/promises';
 import { execFile } from 'node:child_process';
 import { readFileSync } from 'node:fs';
-import { Context, FinalVerdict, RunCodeResult, RunCompiledCodeResult, TestCase } from './runner-lib.ts';
+import { Context, FinalVerdict, InteractiveProcess, RunCodeResult, RunCompiledCodeResult, RunOptions, StartInteractiveResult, TestCase, decodeBase64, encodeBase64 } from './runner-lib.ts';
 
 type Lang = {
     name: string,
@@ -15,106 +15,438 @@ type Lang = {
 };
 
 type Input = {
+    /** Base64 encoded */
     code: string,
     lang: Lang,
     judge: string
 }
 
-const { code, lang, judge }: Input = await new Response(Deno.stdin.readable).json();
+/**
+ * The first line of stdin is the input, after that the lang runner answers requests with
+ * one line each. Lines are only read when needed, a pending read would keep Deno running.
+ */
+const stdinLines = (async function* () {
+    const decoder = new TextDecoder();
+    let buffer = '';
+    for await (const chunk of Deno.stdin.readable) {
+        buffer += decoder.decode(chunk, { stream: true });
+        let newline;
+        while ((newline = buffer.indexOf('\n')) !== -1) {
+            yield buffer.slice(0, newline);
+            buffer = buffer.slice(newline + 1);
+        }
+    }
+})();
+
+const { code, lang, judge }: Input = JSON.parse((await stdinLines.next()).value ?? '');
+
+/**
+ * Programs run concurrently with extra slots from the lang runner, so they count against its
+ * limit on concurrent runs. The judge's own slot runs one program at a time, programs wait
+ * for it when the lang runner has no free slots.
+ */
+const slots = (() => {
+    let ownSlotFree = true;
+    const waiting: (() => void)[] = [];
+    let requests: Promise<unknown> = Promise.resolve();
+
+    const requestSlot = (): Promise<boolean> => {
+        const reply = requests.then(async () => {
+            console.log(JSON.stringify('AcquireSlot'));
+            return JSON.parse((await stdinLines.next()).value ?? 'false') === true;
+        });
+        requests = reply.catch(() => {});
+        return reply;
+    }
+
+    const releaseOwnSlot = () => {
+        const next = waiting.shift();
+        if (next) {
+            next();
+        } else {
+            ownSlotFree = true;
+        }
+    }
+
+    /** Resolves with a function that gives the slot back */
+    return async (): Promise<() => void> => {
+        if (ownSlotFree) {
+            ownSlotFree = false;
+            return releaseOwnSlot;
+        }
+        if (await requestSlot()) {
+            return () => console.log(JSON.stringify('ReleaseSlot'));
+        }
+        if (ownSlotFree) {
+            ownSlotFree = false;
+        } else {
+            await new Promise<void>((resolve) => waiting.push(resolve));
+        }
+        return releaseOwnSlot;
+    }
+})();
+
+/** Where the language of the code is, the sandbox usually mounts it at /lang */
+const LANG_LOCATION = Deno.env.get('YQ_LANG_LOCATION') ?? '/lang';
+const TMP_DIR = Deno.env.get('TMPDIR') ?? '/tmp';
+
+// Clock ticks per second in /proc, this is 100 on every Linux architecture we run on
+const USER_HZ = 100;
+
+/**
+ * The CPU time of all children that have been waited for. Deno has no API for the resource
+ * usage of a child process, so this is the only way to get it.
+ */
+const childrenCpuTimeMs = (): number | undefined => {
+    try {
+        const stat = Deno.readTextFileSync('/proc/self/stat');
+        // The fields after the command name, which can contain spaces, starting with the state
+        const fields = stat.slice(stat.lastIndexOf(')') + 2).split(' ');
+        const [cutime, cstime] = [fields[13], fields[14]].map(Number);
+        return (cutime + cstime) * 1000 / USER_HZ;
+    } catch {
+        return undefined;
+    }
+}
+
+/** Programs that are running, so CPU time that other programs could have added isn't reported */
+const runningPrograms = new Set<{ overlapped: boolean }>();
+
+/**
+ * Starts measuring the CPU time of a program, call the result once it has been waited for.
+ * The children's CPU time covers every program, so it's undefined if another program ran
+ * at the same time.
+ */
+const measureCpuTime = (): () => number | undefined => {
+    const program = { overlapped: runningPrograms.size > 0 };
+    for (const other of runningPrograms) {
+        other.overlapped = true;
+    }
+    runningPrograms.add(program);
+    const cpuTimeBefore = childrenCpuTimeMs();
+    return () => {
+        runningPrograms.delete(program);
+        const cpuTimeAfter = childrenCpuTimeMs();
+        return program.overlapped || cpuTimeBefore === undefined || cpuTimeAfter === undefined
+            ? undefined
+            : cpuTimeAfter - cpuTimeBefore;
+    };
+}
 
-const run = async (args: string[], env: [string, string][], input: string): Promise<RunCodeResult> => {
+const run = async (args: string[], env: [string, string][], cwd: string, input: Uint8Array): Promise<RunCodeResult> => {
     const command = new Deno.Command(
         args[0],
         {
             args: args.slice(1),
+            cwd,
             stdin: 'piped',
             stdout: 'piped',
             stderr: 'piped',
+            clearEnv: true,
             env: Object.fromEntries(env)
         }
     )
 
+    const startTime = performance.now();
     const process = command.spawn();
+    const cpuTime = measureCpuTime();
     const writer = process.stdin.getWriter();
-    await writer.write(new TextEncoder().encode(input));
+    await writer.write(input);
     await writer.close();
     const { code, stdout, stderr } = await process.output();
+    const wallTimeMs = performance.now() - startTime;
+    const cpuTimeMs = cpuTime();
     const textDecoder = new TextDecoder();
     return {
         exitStatus: code,
         stdout: textDecoder.decode(stdout),
-        stderr: textDecoder.decode(stderr)
+        stderr: textDecoder.decode(stderr),
+        stdoutBytes: stdout,
+        stderrBytes: stderr,
+        usage: {
+            wallTimeMs,
+            cpuTimeMs,
+        }
     }
 }
 
-const compile_and_run_program = (() => {
-    const compiled_programs: Record<string, string> = {};
+const concatBytes = (a: Uint8Array, b: Uint8Array): Uint8Array => {
+    const result = new Uint8Array(a.length + b.length);
+    result.set(a);
+    result.set(b, a.length);
+    return result;
+}
 
-    const replaceTokens = (ar: string[], outputLocation: string) => ar.map((e) => {
-        return e.replace(/\$\{LANG_LOCATION\}/ug, '/lang')
-            .replace(/\$\{FILE_LOCATION\}/ug, '/tmp/code')
-            .replace(/\$\{OUTPUT_LOCATION\}/ug, outputLocation);
-    })
+/**
+ * Starts a program that the judge talks to in turns. Output is read in the background,
+ * so reading a line can be given up on after a timeout without losing any output.
+ */
+const spawn_interactive = (args: string[], env: [string, string][], cwd: string): InteractiveProcess => {
+    const command = new Deno.Command(
+        args[0],
+        {
+            args: args.slice(1),
+            cwd,
+            stdin: 'piped',
+            stdout: 'piped',
+            stderr: 'piped',
+            clearEnv: true,
+            env: Object.fromEntries(env)
+        }
+    )
 
-    return async (lang: Lang, code: string, input: string): Promise<RunCompiledCodeResult> => {
-        let compilationResult: RunCodeResult | undefined;
+    const startTime = performance.now();
+    const process = command.spawn();
+    const cpuTime = measureCpuTime();
+    const writer = process.stdin.getWriter();
+    const stderr = new Response(process.stderr).arrayBuffer();
 
-        if (!Object.hasOwn(compiled_programs, code) && lang.compileCommand.length > 0) {
-            const codeIndex = Object.keys(compiled_programs).length
-            const outputLocation = `/tmp/executable${codeIndex}`;
-            compilationResult = await run(
-                replaceTokens(lang.compileCommand, outputLocation),
-                lang.env,
-                ""
-            )
-            if (compilationResult.exitStatus === 0) {
-                compiled_programs[code] = outputLocation;
-            } else {
-                return {
-                    compilationResult,
-                    stdout: "",
-                    stderr: "",
-                    exitStatus: 1
+    let buffer = new Uint8Array();
+    let closed = false;
+    let notify = () => {};
+    const pump = (async () => {
+        for await (const chunk of process.stdout) {
+            buffer = concatBytes(buffer, chunk);
+            notify();
+        }
+        closed = true;
+        notify();
+    })();
+
+    return {
+        write: (data) => writer.write(data),
+        readLine: async () => {
+            for (;;) {
+                const newline = buffer.indexOf(10);
+                if (newline !== -1) {
+                    const line = buffer.slice(0, newline + 1);
+                    buffer = buffer.slice(newline + 1);
+                    return line;
+                }
+                if (closed) {
+                    const rest = buffer;
+                    buffer = new Uint8Array();
+                    return rest.length > 0 ? rest : undefined;
                 }
+                await new Promise<void>((resolve) => { notify = resolve; });
             }
+        },
+        closeStdin: async () => {
+            try {
+                await writer.close();
+            } catch {
+                // The program already exited or closed its input
+            }
+        },
+        kill: () => {
+            try {
+                process.kill('SIGKILL');
+            } catch {
+                // The program already exited
+            }
+        },
+        wait: async () => {
+            const { code } = await process.status;
+            await pump;
+            const wallTimeMs = performance.now() - startTime;
+            const cpuTimeMs = cpuTime();
+            const remainingOutput = buffer;
+            buffer = new Uint8Array();
+            return {
+                exitStatus: code,
+                remainingOutput,
+                stderrBytes: new Uint8Array(await stderr),
+                usage: {
+                    wallTimeMs,
+                    cpuTimeMs,
+                }
+            };
         }
+    };
+}
+
+const textEncoder = new TextEncoder();
+const toBytes = (value: string | Uint8Array) => typeof value === 'string' ? textEncoder.encode(value) : value;
 
-        const outputLocation = Object.hasOwn(compiled_programs, code) ? compiled_programs[code] : '/tmp/output';
+type RunCommand = { args: string[], env: [string, string][], cwd: string };
 
-        const { stdout, stderr, exitStatus } = await run(
-            replaceTokens(lang.runCommand, outputLocation),
-            lang.env,
-            input
-        );
+const program_runner = (() => {
+    /** Compilations by base64 encoded code, shared by runs of the same code */
+    const compiled_programs: Record<string, Promise<{ compilationResult: RunCodeResult, outputLocation: string | undefined }>> = {};
+    let compilations = 0;
+    let runDirectories = 0;
+
+    const replaceTokens = (ar: string[], directory: string, outputLocation: string) => ar.map((e) => {
+        return e.replace(/\$\{LANG_LOCATION\}/ug, LANG_LOCATION)
+            .replace(/\$\{FILE_LOCATION\}/ug, `${directory}/code`)
+            .replace(/\$\{OUTPUT_LOCATION\}/ug, outputLocation);
+    })
+    const replaceEnvTokens = (env: [string, string][], directory: string, outputLocation: string) => env.map(
+        ([key, value]): [string, string] => [key, replaceTokens([value], directory, outputLocation)[0]]
+    )
+    /** `${ARGS}` is a whole argument, when the command doesn't have it the arguments go last */
+    const insertArgs = (command: string[], args: string[]) => command.includes('${ARGS}')
+        ? command.flatMap((e) => e === '${ARGS}' ? args : [e])
+        : [...command, ...args];
+
+    /**
+     * Every program run gets its own working directory with the code and input files, so
+     * runs can happen at the same time
+     */
+    const createRunDirectory = async (code: Uint8Array, files: RunOptions['files']): Promise<string> => {
+        for (const name of Object.keys(files)) {
+            if (!/^\w[\w.-]*$/u.test(name) || /^(code|output)$/u.test(name)) {
+                throw new Error(`Invalid input file name ${JSON.stringify(name)}`);
+            }
+        }
+        const directory = `${TMP_DIR}/run${runDirectories++}`;
+        await mkdir(directory);
+        await writeFile(`${directory}/code`, code);
+        for (const [name, content] of Object.entries(files)) {
+            await writeFile(`${directory}/${name}`, toBytes(content));
+        }
+        return directory;
+    }
+
+    const removeRunDirectory = (directory: string) => rm(directory, { recursive: true, force: true });
+
+    /**
+     * Compiles the code if the language needs it and it wasn't compiled before, and returns
+     * the command to run it, or undefined if compilation failed
+     */
+    const compile = async (lang: Lang, code: Uint8Array, directory: string, args: string[]): Promise<{
+        compilationResult: RunCodeResult | undefined,
+        runCommand: RunCommand | undefined
+    }> => {
+        let compilationResult: RunCodeResult | undefined;
+        let outputLocation = `${directory}/output`;
+
+        if (lang.compileCommand.length > 0) {
+            const codeKey = encodeBase64(code);
+            if (!Object.hasOwn(compiled_programs, codeKey)) {
+                const executable = `${TMP_DIR}/executable${compilations++}`;
+                compiled_programs[codeKey] = run(
+                    replaceTokens(lang.compileCommand, directory, executable),
+                    replaceEnvTokens(lang.env, directory, executable),
+                    directory,
+                    new Uint8Array()
+                ).then((result) => {
+                    if (result.exitStatus !== 0) {
+                        // Show the compiler output again on the next run
+                        delete compiled_programs[codeKey];
+                    }
+                    return { compilationResult: result, outputLocation: result.exitStatus === 0 ? executable : undefined };
+                });
+            }
+            const compilation = await compiled_programs[codeKey];
+            if (compilation.outputLocation === undefined) {
+                return { compilationResult: compilation.compilationResult, runCommand: undefined };
+            }
+            compilationResult = compilation.compilationResult;
+            outputLocation = compilation.outputLocation;
+        }
 
         return {
-            stdout,
-            stderr,
-            exitStatus,
             compilationResult,
-        }
+            runCommand: {
+                args: insertArgs(replaceTokens(lang.runCommand, directory, outputLocation), args),
+                env: replaceEnvTokens(lang.env, directory, outputLocation),
+                cwd: directory
+            }
+        };
     }
+
+    return {
+        run: async (lang: Lang, code: Uint8Array, input: Uint8Array, options: RunOptions): Promise<RunCompiledCodeResult> => {
+            const releaseSlot = await slots();
+            const directory = await createRunDirectory(code, options.files);
+            try {
+                const { compilationResult, runCommand } = await compile(lang, code, directory, options.args);
+                if (runCommand === undefined) {
+                    return {
+                        compilationResult,
+                        stdout: "",
+                        stderr: "",
+                        stdoutBytes: new Uint8Array(),
+                        stderrBytes: new Uint8Array(),
+                        exitStatus: 1,
+                        usage: { wallTimeMs: 0 }
+                    }
+                }
+
+                const { stdout, stderr, stdoutBytes, stderrBytes, exitStatus, usage } = await run(
+                    runCommand.args,
+                    runCommand.env,
+                    runCommand.cwd,
+                    input
+                );
+
+                return {
+                    stdout,
+                    stderr,
+                    stdoutBytes,
+                    stderrBytes,
+                    exitStatus,
+                    usage,
+                    compilationResult,
+                }
+            } finally {
+                await removeRunDirectory(directory);
+                releaseSlot();
+            }
+        },
+        /** The slot and working directory are held until the program is waited for */
+        interact: async (lang: Lang, code: Uint8Array, options: RunOptions): Promise<StartInteractiveResult> => {
+            const releaseSlot = await slots();
+            const directory = await createRunDirectory(code, options.files);
+            const cleanUp = async () => {
+                await removeRunDirectory(directory);
+                releaseSlot();
+            }
+            try {
+                const { compilationResult, runCommand } = await compile(lang, code, directory, options.args);
+                if (runCommand === undefined) {
+                    await cleanUp();
+                    return { compilationResult, process: undefined };
+                }
+                const process = spawn_interactive(runCommand.args, runCommand.env, runCommand.cwd);
+                return {
+                    compilationResult,
+                    process: {
+                        ...process,
+                        wait: async () => {
+                            try {
+                                return await process.wait();
+                            } finally {
+                                await cleanUp();
+                            }
+                        }
+                    }
+                };
+            } catch (e) {
+                await cleanUp();
+                throw e;
+            }
+        }
+    };
 })();
 
 (async () => {
     const judge_function = (
         await import('data:text/typescript,' + encodeURIComponent(
-            readFileSync('/scripts/runner-lib.ts') +
+            readFileSync(new URL('./runner-lib.ts', import.meta.url)) +
             '\nexport default ' +
             judge
         ))
     ).default as ((code: Context) => AsyncGenerator<TestCase, FinalVerdict, undefined>);
 
-    const on_run_callback = async (program: string, input?: string | undefined): Promise<RunCompiledCodeResult> => {
-        await writeFile('/tmp/code', program);
-        return await compile_and_run_program(
-            lang,
-            program,
-            input ?? ''
-        );
-    };
+    const on_run_callback = (program: string | Uint8Array, input: string | Uint8Array | undefined, options: RunOptions): Promise<RunCompiledCodeResult> =>
+        program_runner.run(lang, toBytes(program), toBytes(input ?? ''), options);
+
+    const on_interact_callback = (program: string | Uint8Array, options: RunOptions): Promise<StartInteractiveResult> =>
+        program_runner.interact(lang, toBytes(program), options);
 
-    const generator = judge_function(new Context(code, on_run_callback));
+    const generator = judge_function(new Context(decodeBase64(code), on_run_callback, on_interact_callback));
 
     let value: IteratorResult<TestCase, FinalVerdict>;
     while (!(value = await generator.next()).done) {
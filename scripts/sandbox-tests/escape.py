@@ -0,0 +1,143 @@
+"""Tries to escape the sandbox or attack the kernel from inside it. Prints one JSON line per
+attempt, with whether it was blocked. Run by judge.py."""
+
+import ctypes
+import errno
+import json
+import os
+import platform
+import signal
+
+# The syscall numbers differ per architecture
+SYSCALLS = {
+    "x86_64": {
+        "ptrace": 101,
+        "process_vm_readv": 310,
+        "mount": 165,
+        "umount2": 166,
+        "pivot_root": 155,
+        "chroot": 161,
+        "unshare": 272,
+        "setns": 308,
+        "clone": 56,
+        "clone3": 435,
+        "keyctl": 250,
+        "add_key": 248,
+        "bpf": 321,
+        "perf_event_open": 298,
+        "userfaultfd": 323,
+        "io_uring_setup": 425,
+        "reboot": 169,
+        "syslog": 103,
+    },
+    "aarch64": {
+        "ptrace": 117,
+        "process_vm_readv": 270,
+        "mount": 40,
+        "umount2": 39,
+        "pivot_root": 41,
+        "chroot": 51,
+        "unshare": 97,
+        "setns": 268,
+        "clone": 220,
+        "clone3": 435,
+        "keyctl": 219,
+        "add_key": 217,
+        "bpf": 280,
+        "perf_event_open": 241,
+        "userfaultfd": 282,
+        "io_uring_setup": 425,
+        "reboot": 142,
+        "syslog": 116,
+    },
+}[platform.machine()]
+
+CLONE_NEWUSER = 0x10000000
+KEY_SPEC_PROCESS_KEYRING = -2
+
+libc = ctypes.CDLL(None, use_errno=True)
+libc.syscall.restype = ctypes.c_long
+
+
+def report(name, blocked, detail):
+    print(json.dumps({"name": name, "blocked": blocked, "detail": detail}), flush=True)
+
+
+def syscall(name, *args, allowed_errors=(errno.EPERM,)):
+    """Makes the syscall, it's blocked if it fails with one of `allowed_errors`. The arguments
+    are valid, so other errors mean the kernel looked at them."""
+    converted = [
+        ctypes.c_char_p(arg) if isinstance(arg, bytes) else ctypes.c_long(arg)
+        for arg in args
+    ]
+    result = libc.syscall(ctypes.c_long(SYSCALLS[name]), *converted)
+    if result == 0 and name == "clone":
+        # The clone succeeded and this is the child
+        os._exit(0)
+    if result >= 0:
+        report(name, False, f"{name} returned {result}")
+        return
+    error = ctypes.get_errno()
+    report(
+        name,
+        error in allowed_errors,
+        f"{name} failed with {errno.errorcode.get(error, error)}",
+    )
+
+
+def rootfs(name, check):
+    try:
+        blocked, detail = check()
+    except OSError as e:
+        blocked, detail = True, str(e)
+    report(f"rootfs: {name}", blocked, detail)
+
+
+def setns():
+    try:
+        namespace = os.open("/proc/self/ns/mnt", os.O_RDONLY)
+    except OSError as e:
+        report("setns", True, f"No namespace to join: {e}")
+        return
+    syscall("setns", namespace, 0)
+
+
+def try_write(path):
+    with open(os.path.join(path, "escape"), "w") as file:
+        file.write("escaped")
+    return False, f"Wrote to {path}"
+
+
+syscall("ptrace", 0, 0, 0, 0)  # PTRACE_TRACEME
+syscall("process_vm_readv", os.getppid(), 0, 0, 0, 0, 0)
+syscall("mount", b"none", b"/tmp", b"tmpfs", 0, 0)
+syscall("umount2", b"/tmp", 0)
+syscall("pivot_root", b"/tmp", b"/tmp")
+syscall("chroot", b"/tmp")
+syscall("unshare", CLONE_NEWUSER)
+setns()
+syscall("clone", CLONE_NEWUSER | signal.SIGCHLD, 0, 0, 0, 0)
+syscall("clone3", 0, 0, allowed_errors=(errno.EPERM, errno.ENOSYS))
+syscall("keyctl", 0, KEY_SPEC_PROCESS_KEYRING, 1)  # KEYCTL_GET_KEYRING_ID
+syscall("add_key", b"user", b"escape", b"escape", 6, KEY_SPEC_PROCESS_KEYRING)
+syscall("bpf", 0, 0, 0)  # BPF_MAP_CREATE
+syscall("perf_event_open", 0, 0, -1, -1, 0)
+syscall("userfaultfd", 0)
+syscall("io_uring_setup", 1, 0)
+syscall("reboot", 0xFEE1DEAD, 672274793, 0, 0)  # LINUX_REBOOT_CMD_CAD_OFF
+syscall("syslog", 10, 0, 0)  # SYSLOG_ACTION_SIZE_BUFFER
+
+rootfs(
+    "/etc/passwd is hidden",
+    lambda: (not os.path.exists("/etc/passwd"), "/etc/passwd exists"),
+)
+rootfs(
+    "/usr/bin is hidden",
+    lambda: (not os.path.exists("/usr/bin"), str(os.listdir("/usr/bin")[:10])),
+)
+rootfs(
+    "/home only has the sandbox home",
+    lambda: (os.listdir("/home") == ["yq"], str(os.listdir("/home"))),
+)
+rootfs("/lang is read only", lambda: try_write("/lang"))
+rootfs("/scripts is read only", lambda: try_write("/scripts"))
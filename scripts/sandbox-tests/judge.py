@@ -0,0 +1,19 @@
+import json
+
+
+def judge(context):
+    result = context.run()
+    attempts = [json.loads(line) for line in result.text.splitlines()]
+    if not attempts:
+        yield context.register_test_case(
+            TestCase("Escape attempts", "Fail", {"Text": result.stderr})
+        )
+    for attempt in attempts:
+        yield context.register_test_case(
+            TestCase(
+                attempt["name"],
+                "Pass" if attempt["blocked"] else "Fail",
+                {"Text": attempt["detail"]},
+            )
+        )
+    return context.no_failures()
@@ -0,0 +1,7 @@
+#!/bin/sh
+# Runs escape.py in the sandbox and checks that every escape attempt fails. Run it where the
+# lang runner runs, with LANG_RUNNER set to the lang runner binary. Extra arguments are passed
+# on to `lang-runner run`.
+set -e
+cd "$(dirname "$0")"
+exec "${LANG_RUNNER:-lang-runner}" run --lang python --code escape.py --judge judge.py "$@"